@@ -20,3 +20,12 @@ where
         Ok(default)
     }
 }
+
+/// Writes a value in a format `read_from` can read back
+pub fn write_to<T>(path: &str, value: &T) -> Result<(), Box<dyn std::error::Error>>
+where
+    T: Serialize,
+{
+    std::fs::write(path, serde_json::to_string_pretty(value)?)?;
+    Ok(())
+}
@@ -4,14 +4,22 @@ use bevy::prelude::*;
 use rand::Rng;
 
 use crate::{
-    ant_hill::HillEvents,
-    ants::{AntState, Creature},
-    food::{FoodHeap, FoodPellet, WorldEvents},
+    ant_hill::{AntHill, HillEvent, HillEvents},
+    ants::Creature,
+    combat::{Attack, Health, ANTEATER_HEALTH},
+    food::{FoodHeap, WorldEvents},
     game_state::GameState,
     terrain_spawner::{EmptyLot, ObstacleMap},
-    DEF,
+    BORDER, DEF,
 };
 
+/// Pellets left by a dead anteater, on top of half the food it ate
+const CARCASS_FOOD: usize = 150;
+/// Of the ant body scaled up, checked against obstacles
+const ANTEATER_RADIUS: f32 = 0.075;
+/// Anteaters caught in an obstacle are moved to free ground up to this far
+const UNSTUCK_RANGE: f32 = 0.3;
+
 pub struct AntEatersPlugin;
 
 impl Plugin for AntEatersPlugin {
@@ -20,11 +28,11 @@ impl Plugin for AntEatersPlugin {
             .add_system_set(
                 SystemSet::on_update(GameState::Playing)
                     .with_system(spawn_anteaters)
+                    .with_system(anteaters_decide)
                     .with_system(move_anteaters),
             )
             .add_system_to_stage(CoreStage::PostUpdate, anteaters_die)
-            .add_system_to_stage(CoreStage::Update, anteaters_consume_food)
-            .add_system_to_stage(CoreStage::PreUpdate, anteaters_consume_ants);
+            .add_system_to_stage(CoreStage::Update, anteaters_consume_food);
     }
 }
 
@@ -83,6 +91,43 @@ pub struct AntEater {
     pub wander_strength: f32,
     pub food_picked: u32,
     pub ant_killed: u32,
+    pub state: AntEaterState,
+}
+
+impl AntEater {
+    fn is_satiated(&self) -> bool {
+        self.ant_killed >= behaviour::SATIATED_ANTS || self.food_picked >= behaviour::SATIATED_FOOD
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum AntEaterState {
+    /// Wander towards the nearest hill
+    Roam,
+    /// Follow where ants are the most numerous
+    Track(Vec3),
+    /// Stay on a food heap or a column of ants
+    Feed(Vec3),
+    /// Satiated, leave the map by the nearest border
+    Retreat(Vec3),
+    /// Run away from a swarm of ants until the given time
+    Flee { from: Vec3, until: f64 },
+}
+
+mod behaviour {
+    /// Distance at which ants are noticed
+    pub const SENSE: f32 = 0.6;
+    /// Ants needed within `SENSE` to start tracking them
+    pub const TRACK: usize = 5;
+    /// Distance at which food and ants can be eaten from
+    pub const FEED_RANGE: f32 = 0.15;
+    /// Ants needed within `FEED_RANGE` to feed on them
+    pub const COLUMN: usize = 8;
+    /// Ants within `FEED_RANGE` that make the anteater flee
+    pub const SWARM: usize = 30;
+    pub const FLEE_TIME: f64 = 3.0;
+    pub const SATIATED_ANTS: u32 = 40;
+    pub const SATIATED_FOOD: u32 = 200;
 }
 
 fn spawn_anteaters(
@@ -92,7 +137,12 @@ fn spawn_anteaters(
 ) {
     for event in events.iter() {
         match event {
-            WorldEvents::SpawnFood(_) => (),
+            WorldEvents::SpawnFood(_)
+            | WorldEvents::PlaceFood(_)
+            | WorldEvents::SpawnCarcass(_, _)
+            | WorldEvents::SpawnPredator(_, _)
+            | WorldEvents::PlaceBlueprint(_, _)
+            | WorldEvents::Dig(_) => (),
             WorldEvents::SpawnAntEater(position) => {
                 commands
                     .spawn_bundle((
@@ -129,29 +179,135 @@ fn spawn_anteaters(
                         wander_strength: 0.2,
                         food_picked: 0,
                         ant_killed: 0,
-                    });
+                        state: AntEaterState::Roam,
+                    })
+                    .insert_bundle((Health::new(ANTEATER_HEALTH), Attack::of_anteater()));
             }
         }
     }
 }
 
+fn anteaters_decide(
+    mut anteaters: Query<(&Transform, &mut AntEater)>,
+    ants: Query<&Transform, With<Creature>>,
+    food_heaps: Query<(&Transform, &FoodHeap)>,
+    time: Res<Time>,
+    obstacle_map: Res<ObstacleMap>,
+) {
+    let now = time.seconds_since_startup();
+    for (transform, mut anteater) in anteaters.iter_mut() {
+        let position = transform.translation;
+        if let AntEaterState::Retreat(_) = anteater.state {
+            continue;
+        }
+        if anteater.is_satiated() {
+            let exit = Vec3::new(position.x, 0.0, position.z).normalize_or_zero();
+            let exit = if exit == Vec3::ZERO { Vec3::X } else { exit };
+            anteater.state = AntEaterState::Retreat(exit * BORDER * 1.5);
+            continue;
+        }
+
+        let around = |range: f32| {
+            let (count, sum) = ants
+                .iter()
+                .map(|ant| ant.translation)
+                .filter(|ant| ant.distance_squared(position) < range.powf(2.0))
+                .fold((0, Vec3::ZERO), |(count, sum), ant| (count + 1, sum + ant));
+            (count, sum / count.max(1) as f32)
+        };
+        let (close, close_center) = around(behaviour::FEED_RANGE);
+        if close >= behaviour::SWARM {
+            anteater.state = AntEaterState::Flee {
+                from: close_center,
+                until: now + behaviour::FLEE_TIME,
+            };
+            continue;
+        }
+        if let AntEaterState::Flee { until, .. } = anteater.state {
+            if now < until {
+                continue;
+            }
+        }
+
+        let heap = food_heaps
+            .iter()
+            .find(|(heap, food)| {
+                food.available() > 0
+                    && heap.translation.distance_squared(position) < behaviour::FEED_RANGE.powf(2.0)
+            })
+            .map(|(heap, _)| heap.translation);
+        let (sensed, sensed_center) = around(behaviour::SENSE);
+        // only columns it can walk straight to are tracked
+        let in_sight = || {
+            obstacle_map.is_path_clear(
+                Vec2::new(position.x, position.z),
+                Vec2::new(sensed_center.x, sensed_center.z),
+                ANTEATER_RADIUS,
+            )
+        };
+        anteater.state = if let Some(heap) = heap {
+            AntEaterState::Feed(heap)
+        } else if close >= behaviour::COLUMN {
+            AntEaterState::Feed(close_center)
+        } else if sensed >= behaviour::TRACK && in_sight() {
+            AntEaterState::Track(sensed_center)
+        } else {
+            AntEaterState::Roam
+        };
+    }
+}
+
 fn move_anteaters(
     mut commands: Commands,
     mut anteaters: Query<(&mut Transform, &mut AntEater)>,
+    hills: Query<&Transform, (With<AntHill>, Without<AntEater>)>,
     time: Res<Time>,
     obstacle_map: Res<ObstacleMap>,
 ) {
     let steer_strength = 2.0;
-    let max_speed = 0.18;
     let wander_strength = 0.5;
     for (mut transform, mut anteater) in anteaters.iter_mut() {
-        let moving_towards = transform.translation.normalize()
-            + Quat::from_rotation_y(rand::thread_rng().gen_range(0.0..(2.0 * PI)))
-                .mul_vec3(Vec3::X)
-                * anteater.wander_strength;
+        let random = Quat::from_rotation_y(rand::thread_rng().gen_range(0.0..(2.0 * PI)))
+            .mul_vec3(Vec3::X)
+            * anteater.wander_strength;
+        let towards = |target: Vec3| {
+            (transform.translation - Vec3::new(target.x, 0.0, target.z)).normalize_or_zero()
+        };
+        let (moving_towards, max_speed) = match anteater.state {
+            AntEaterState::Roam => {
+                let nearest_hill = hills
+                    .iter()
+                    .map(|hill| Vec3::new(hill.translation.x, 0.0, hill.translation.z))
+                    .min_by(|a, b| {
+                        a.distance_squared(transform.translation)
+                            .partial_cmp(&b.distance_squared(transform.translation))
+                            .unwrap()
+                    })
+                    .unwrap_or(Vec3::ZERO);
+                (towards(nearest_hill) + random, 0.18)
+            }
+            AntEaterState::Track(target) => (towards(target) + random / 2.0, 0.22),
+            AntEaterState::Feed(spot) => {
+                // slow down and circle around the spot
+                let distance = transform.translation.distance(spot) / behaviour::FEED_RANGE;
+                (towards(spot) * distance.min(1.0) + random / 2.0, 0.05)
+            }
+            AntEaterState::Retreat(exit) => (towards(exit) + random / 4.0, 0.25),
+            AntEaterState::Flee { from, .. } => (-towards(from) + random / 4.0, 0.3),
+        };
         anteater.desired_direction = (anteater.desired_direction - moving_towards).normalize();
+        // anteaters lean towards easier ground ahead of them
+        let heading = obstacle_map.easiest_heading(
+            transform.translation.x,
+            transform.translation.z,
+            anteater.desired_direction,
+            2.0 / DEF,
+            ANTEATER_RADIUS,
+        );
+        let max_speed = max_speed
+            * obstacle_map.speed_factor(transform.translation.x, transform.translation.z, heading);
 
-        let desired_velocity = anteater.desired_direction * max_speed;
+        let desired_velocity = heading * max_speed;
         let desired_steering_force = (desired_velocity - anteater.velocity) * steer_strength;
         let acceleration = desired_steering_force.clamp_length_max(steer_strength);
 
@@ -165,7 +321,7 @@ fn move_anteaters(
         };
         let forward = transform.translation + anteater.velocity * time.delta_seconds();
         let forward_forward = transform.translation + anteater.velocity / DEF * 2.0;
-        if !obstacle_map.is_obstacle(forward_forward.x, forward_forward.z, 0.0) {
+        if !obstacle_map.is_obstacle(forward_forward.x, forward_forward.z, ANTEATER_RADIUS) {
             transform.rotation = Quat::from_rotation_y(angle);
             transform.translation = forward;
             anteater.wander_strength = wander_strength;
@@ -176,77 +332,81 @@ fn move_anteaters(
             commands.spawn_bundle((EmptyLot::new(position, true),));
         } else {
             anteater.wander_strength += 0.5;
+            let (x, z) = (transform.translation.x, transform.translation.z);
+            if obstacle_map.is_obstacle(x, z, ANTEATER_RADIUS) {
+                if let Some(free) = obstacle_map.nearest_free(x, z, ANTEATER_RADIUS, UNSTUCK_RANGE)
+                {
+                    transform.translation.x = free.x;
+                    transform.translation.z = free.y;
+                }
+            }
         }
     }
 }
 
 fn anteaters_die(
     mut commands: Commands,
-    anteaters: Query<(Entity, &Transform, &AntEater)>,
-    mut events: EventWriter<HillEvents>,
+    anteaters: Query<(Entity, &Transform, &AntEater, &Health)>,
+    hills: Query<(Entity, &Transform), With<AntHill>>,
+    mut events: EventWriter<HillEvent>,
+    mut world_events: EventWriter<WorldEvents>,
 ) {
-    for (entity, transform, anteater) in anteaters.iter() {
-        if transform.translation.distance_squared(Vec3::ZERO) < 0.005 {
+    for (entity, transform, anteater, health) in anteaters.iter() {
+        // killed by the ants, leaving a heap of food behind
+        if health.is_dead() {
             commands.entity(entity).despawn_recursive();
-            events.send(HillEvents::ReplenishFood(
-                anteater.ant_killed / 15,
-                1.0,
-                None,
-            ));
-            events.send(HillEvents::ReplenishFood(
-                anteater.food_picked / 25,
-                0.8,
-                None,
+            world_events.send(WorldEvents::SpawnCarcass(
+                transform.translation,
+                CARCASS_FOOD + anteater.food_picked as usize / 2,
             ));
-            events.send(HillEvents::ImproveLifeExpectancy(-0.8));
-            events.send(HillEvents::ImproveMaxSpeed(-0.002));
-            events.send(HillEvents::ImproveAntennas(-0.1));
+            continue;
         }
-    }
-}
-
-fn anteaters_consume_food(
-    mut commands: Commands,
-    mut anteaters: Query<(Entity, &Transform, &mut AntEater)>,
-    foods: Query<(Entity, &GlobalTransform), With<FoodPellet>>,
-) {
-    for (entity, transform, mut anteater) in anteaters.iter_mut() {
-        for (food_entity, food_transform) in foods.iter() {
+        // satiated anteaters leave without a fight
+        if let AntEaterState::Retreat(_) = anteater.state {
             if transform
                 .translation
-                .distance_squared(food_transform.translation)
-                < 0.017
+                .x
+                .abs()
+                .max(transform.translation.z.abs())
+                > BORDER - 0.1
             {
-                commands
-                    .entity(food_entity)
-                    .insert(Parent(entity))
-                    .remove::<Transform>()
-                    .remove::<FoodPellet>();
-                anteater.food_picked += 1;
+                commands.entity(entity).despawn_recursive();
+            }
+            continue;
+        }
+        let reached = hills.iter().find(|(_, hill)| {
+            Vec3::new(hill.translation.x, 0.0, hill.translation.z)
+                .distance_squared(transform.translation)
+                < 0.005
+        });
+        if let Some((hill, _)) = reached {
+            commands.entity(entity).despawn_recursive();
+            for event in [
+                HillEvents::ReplenishFood(anteater.ant_killed / 15, 1.0, None),
+                HillEvents::ReplenishFood(anteater.food_picked / 25, 0.8, None),
+                HillEvents::ImproveLifeExpectancy(-0.8),
+                HillEvents::ImproveMaxSpeed(-0.002),
+                HillEvents::ImproveAntennas(-0.1),
+            ] {
+                events.send(HillEvent { hill, event });
             }
         }
     }
 }
-fn anteaters_consume_ants(
-    mut commands: Commands,
+
+fn anteaters_consume_food(
     mut anteaters: Query<(&Transform, &mut AntEater)>,
-    ants: Query<(Entity, &Transform, &Creature)>,
-    mut foods: Query<&mut FoodPellet, (Without<Creature>, Without<FoodHeap>)>,
+    mut food_heaps: Query<(&Transform, &mut FoodHeap)>,
 ) {
     for (transform, mut anteater) in anteaters.iter_mut() {
-        for (ant_entity, ant_transform, ant) in ants.iter() {
+        for (heap_transform, mut heap) in food_heaps.iter_mut() {
             if transform
                 .translation
-                .distance_squared(ant_transform.translation)
+                .distance_squared(heap_transform.translation)
                 < 0.017
             {
-                if let AntState::PickFood(_, food_entity) = ant.state {
-                    if let Ok(mut food_pellet) = foods.get_mut(food_entity) {
-                        food_pellet.targeted = false;
-                    }
-                }
-                commands.entity(ant_entity).despawn_recursive();
-                anteater.ant_killed += 1;
+                // pellets already promised to an ant are left for it
+                anteater.food_picked += heap.take_available() as u32;
             }
         }
     }
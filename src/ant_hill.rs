@@ -1,12 +1,24 @@
-use std::{collections::VecDeque, f32::consts::FRAC_PI_2, time::Duration};
+use std::{
+    collections::VecDeque,
+    f32::consts::{FRAC_PI_2, PI},
+    time::Duration,
+};
 
-use bevy::prelude::*;
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    ants::{AntHandles, AntState, Creature, CreatureGene},
+    ants::{AntHandles, AntState, Creature, CreatureGene, Energy},
+    combat::{Attack, Health, ANT_HEALTH},
+    food::{FoodHeap, FoodKinds},
     game_state::GameState,
+    terrain_spawner::NoiseSeeds,
     ui::GraphData,
+    BORDER, DEF,
 };
 
 pub struct AntHillPlugin;
@@ -14,13 +26,15 @@ pub struct AntHillPlugin;
 impl Plugin for AntHillPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<AntHillHandles>()
-            .add_event::<HillEvents>()
+            .add_event::<HillEvent>()
             .add_system_set(SystemSet::on_enter(GameState::Playing).with_system(spawn_ant_hill))
             .insert_resource(EvolveTimer(Timer::new(Duration::from_secs_f32(30.0), true)))
             .add_system_set(
                 SystemSet::on_update(GameState::Playing)
-                    .with_system(hill_events)
-                    .with_system(use_food)
+                    .with_system(hill_events.label(HillSystem::Events))
+                    .with_system(send_settlers)
+                    .with_system(settle_nests)
+                    .with_system(brood_cycle.after(HillSystem::Events))
                     // used for debugging
                     // .with_system(spawn_ant)
                     .with_system(evolve_hills),
@@ -28,9 +42,16 @@ impl Plugin for AntHillPlugin {
     }
 }
 
-struct AntHillHandles {
-    mesh: Handle<bevy::render::mesh::Mesh>,
-    color: Handle<bevy::pbr::StandardMaterial>,
+/// Eggs are laid once the events of the frame have been paid for
+#[derive(SystemLabel, Clone, PartialEq, Eq, Hash, Debug)]
+enum HillSystem {
+    Events,
+}
+
+pub struct AntHillHandles {
+    pub mesh: Handle<bevy::render::mesh::Mesh>,
+    pub color: Handle<bevy::pbr::StandardMaterial>,
+    pub rival_color: Handle<bevy::pbr::StandardMaterial>,
 }
 
 impl FromWorld for AntHillHandles {
@@ -54,11 +75,22 @@ impl FromWorld for AntHillHandles {
             metallic: 0.0,
             ..Default::default()
         });
+        let rival_color = materials.add(bevy::pbr::StandardMaterial {
+            base_color: bevy::render::color::Color::rgb(0.35, 0.1, 0.1),
+            perceptual_roughness: 1.0,
+            metallic: 0.0,
+            ..Default::default()
+        });
 
-        Self { mesh, color }
+        Self {
+            mesh,
+            color,
+            rival_color,
+        }
     }
 }
 
+#[derive(Component)]
 pub struct AntHill {
     pub food: u32,
     pub queen_food: u32,
@@ -66,6 +98,12 @@ pub struct AntHill {
     pub spawn_per_wave: f32,
     pub mutation_improvement: f32,
     pub gatherer_genes: VecDeque<CreatureGene>,
+    /// Pellets delivered, by kind of food
+    pub delivered: HashMap<String, u32>,
+    pub brood: Vec<Brood>,
+    laying: f32,
+    /// Eggs laid since the queen last ate
+    clutch: u32,
 }
 
 impl Default for AntHill {
@@ -82,21 +120,58 @@ impl Default for AntHill {
             spawn_per_wave: 10.0,
             mutation_improvement: 0.0,
             gatherer_genes: VecDeque::new(),
+            delivered: HashMap::default(),
+            brood: Vec::new(),
+            laying: 0.0,
+            clutch: 0,
         }
     }
 }
 
-fn spawn_ant_hill(mut commands: Commands, ant_hill_handles: Res<AntHillHandles>) {
-    commands.spawn_bundle(bevy::pbr::PbrBundle {
-        mesh: ant_hill_handles.mesh.clone_weak(),
-        material: ant_hill_handles.color.clone_weak(),
-        transform: Transform::from_xyz(0.0, -0.02, 0.0),
-        ..Default::default()
-    });
+/// The first hill of the player colony, the one upgrades are bought from
+#[derive(Component)]
+pub struct MainHill;
+
+#[derive(Component, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Colony(pub u8);
+
+impl Colony {
+    pub const PLAYER: Colony = Colony(0);
 }
 
+/// A spot chosen for a new hill, waiting for its settlers to arrive
+#[derive(Component)]
+pub struct NestSite {
+    gene: CreatureGene,
+    colony: Colony,
+}
+
+fn spawn_ant_hill(
+    mut commands: Commands,
+    ant_hill_handles: Res<AntHillHandles>,
+    noise_seeds: Res<NoiseSeeds>,
+) {
+    let hill = noise_seeds.hill();
+    commands
+        .spawn_bundle(bevy::pbr::PbrBundle {
+            mesh: ant_hill_handles.mesh.clone_weak(),
+            material: ant_hill_handles.color.clone_weak(),
+            transform: Transform::from_xyz(hill.x, -0.02, hill.y),
+            ..Default::default()
+        })
+        .insert_bundle((AntHill::default(), Colony::PLAYER, MainHill));
+}
+
+pub struct HillEvent {
+    pub hill: Entity,
+    pub event: HillEvents,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum HillEvents {
-    SpawnAnts { count: u32 },
+    SpawnAnts {
+        count: u32,
+    },
     RemoveQueenFood(u32),
     ImproveMaxSpeed(f32),
     ImproveLifeExpectancy(f64),
@@ -104,93 +179,260 @@ pub enum HillEvents {
     ImproveWave(f32),
     ImproveMutation(f32),
     ReplenishFood(u32, f64, Option<CreatureGene>),
+    DeliverFood {
+        kind: String,
+        gene: Option<CreatureGene>,
+    },
+    FoundNest {
+        count: u32,
+    },
 }
 
-fn use_food(mut hill: ResMut<AntHill>, mut events: EventWriter<HillEvents>) {
-    if hill.food >= 10 {
-        hill.food -= 10;
-        events.send(HillEvents::SpawnAnts {
-            count: hill.spawn_per_wave as u32,
-        });
+impl HillEvents {
+    /// Adds `level` times `increase` to this event when both are of the same kind
+    pub fn increased_by(&self, increase: &HillEvents, level: u32) -> HillEvents {
+        match (self, increase) {
+            (HillEvents::SpawnAnts { count }, HillEvents::SpawnAnts { count: increase }) => {
+                HillEvents::SpawnAnts {
+                    count: count + increase * level,
+                }
+            }
+            (HillEvents::RemoveQueenFood(value), HillEvents::RemoveQueenFood(increase)) => {
+                HillEvents::RemoveQueenFood(value + increase * level)
+            }
+            (HillEvents::ImproveMaxSpeed(value), HillEvents::ImproveMaxSpeed(increase)) => {
+                HillEvents::ImproveMaxSpeed(value + increase * level as f32)
+            }
+            (
+                HillEvents::ImproveLifeExpectancy(value),
+                HillEvents::ImproveLifeExpectancy(increase),
+            ) => HillEvents::ImproveLifeExpectancy(value + increase * level as f64),
+            (HillEvents::ImproveAntennas(value), HillEvents::ImproveAntennas(increase)) => {
+                HillEvents::ImproveAntennas(value + increase * level as f32)
+            }
+            (HillEvents::ImproveWave(value), HillEvents::ImproveWave(increase)) => {
+                HillEvents::ImproveWave(value + increase * level as f32)
+            }
+            (HillEvents::ImproveMutation(value), HillEvents::ImproveMutation(increase)) => {
+                HillEvents::ImproveMutation(value + increase * level as f32)
+            }
+            (HillEvents::FoundNest { count }, HillEvents::FoundNest { count: increase }) => {
+                HillEvents::FoundNest {
+                    count: count + increase * level,
+                }
+            }
+            (
+                HillEvents::ReplenishFood(count, ratio, gene),
+                HillEvents::ReplenishFood(increase, _, _),
+            ) => HillEvents::ReplenishFood(count + increase * level, *ratio, *gene),
+            _ => self.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BroodStage {
+    Egg,
+    Larva,
+}
+
+pub struct Brood {
+    pub stage: BroodStage,
+    /// Fixed when the egg is laid
+    gene: CreatureGene,
+    fed: bool,
+    age: f32,
+    starving: f32,
+}
+
+impl AntHill {
+    fn replenish(&mut self, count: u32, queen_ratio: f64) {
+        for _ in 0..count {
+            if rand::thread_rng().gen_bool(queen_ratio) {
+                self.queen_food += 1;
+            } else {
+                self.food += 1;
+            }
+        }
+    }
+
+    fn remember_gatherer(&mut self, gene: CreatureGene) {
+        self.gatherer_genes.push_back(gene);
+        if self.gatherer_genes.len() > 100 {
+            self.gatherer_genes.pop_front();
+        }
+    }
+
+    pub fn brood_count(&self, stage: BroodStage) -> usize {
+        self.brood
+            .iter()
+            .filter(|brood| brood.stage == stage)
+            .count()
+    }
+
+    /// Eggs laid per second, faster when the queen is well fed, none when she is out of food
+    fn laying_rate(&self) -> f32 {
+        if self.queen_food == 0 {
+            return 0.0;
+        }
+        self.spawn_per_wave / 10.0 * (0.5 + self.queen_food.min(20) as f32 / 10.0)
+    }
+}
+
+mod brood {
+    pub const EGG_DURATION: f32 = 4.0;
+    pub const LARVA_DURATION: f32 = 8.0;
+    /// Time brood can go without the hill having any food before being lost
+    pub const STARVATION: f32 = 5.0;
+    /// Eggs laid for each queen food she eats, so that most of it is left for upgrades
+    pub const EGGS_PER_MEAL: u32 = 5;
+}
+
+fn brood_cycle(
+    mut commands: Commands,
+    ant_handles: Res<AntHandles>,
+    mut hills: Query<(Entity, &mut AntHill, &Transform, &Colony)>,
+    time: Res<Time>,
+    mut data: ResMut<GraphData>,
+) {
+    let delta = time.delta_seconds();
+    for (entity, mut hill, transform, colony) in hills.iter_mut() {
+        let hill = &mut *hill;
+
+        // the queen eats while laying, and doesn't lay more eggs than the hill can feed
+        hill.laying += hill.laying_rate() * delta;
+        while hill.laying >= 1.0 {
+            hill.laying -= 1.0;
+            if hill.queen_food == 0 || hill.brood.len() as u32 >= hill.food {
+                continue;
+            }
+            hill.clutch += 1;
+            if hill.clutch >= brood::EGGS_PER_MEAL {
+                hill.clutch = 0;
+                hill.queen_food -= 1;
+            }
+            hill.brood.push(Brood {
+                stage: BroodStage::Egg,
+                gene: mutate(&hill.gene),
+                fed: false,
+                age: 0.0,
+                starving: 0.0,
+            });
+        }
+
+        for brood in hill.brood.iter_mut() {
+            match brood.stage {
+                BroodStage::Egg => {
+                    if hill.food == 0 {
+                        brood.starving += delta;
+                    } else {
+                        brood.starving = 0.0;
+                        brood.age += delta;
+                    }
+                    if brood.age > brood::EGG_DURATION {
+                        brood.stage = BroodStage::Larva;
+                        brood.age = 0.0;
+                    }
+                }
+                BroodStage::Larva => {
+                    if !brood.fed && hill.food > 0 {
+                        hill.food -= 1;
+                        brood.fed = true;
+                    }
+                    if brood.fed {
+                        brood.starving = 0.0;
+                        brood.age += delta;
+                    } else {
+                        brood.starving += delta;
+                    }
+                }
+            }
+        }
+        let has_emerged =
+            |brood: &Brood| brood.stage == BroodStage::Larva && brood.age > brood::LARVA_DURATION;
+        let emerged = hill
+            .brood
+            .iter()
+            .filter(|brood| has_emerged(brood))
+            .map(|brood| brood.gene)
+            .collect::<Vec<_>>();
+        hill.brood
+            .retain(|brood| !has_emerged(brood) && brood.starving < brood::STARVATION);
+
+        if *colony == Colony::PLAYER {
+            data.total_ants += emerged.len() as u32;
+        }
+        for gene in emerged {
+            spawn_creature(
+                &mut commands,
+                &*ant_handles,
+                transform.translation,
+                entity,
+                *colony,
+                gene,
+                time.seconds_since_startup(),
+            );
+        }
     }
 }
 
 #[allow(dead_code)]
-fn spawn_ant(keyboard_input: Res<Input<KeyCode>>, mut events: EventWriter<HillEvents>) {
+fn spawn_ant(
+    keyboard_input: Res<Input<KeyCode>>,
+    main_hill: Query<Entity, With<MainHill>>,
+    mut events: EventWriter<HillEvent>,
+) {
     if keyboard_input.pressed(KeyCode::Space) {
-        events.send(HillEvents::SpawnAnts { count: 1 });
+        events.send(HillEvent {
+            hill: main_hill.single(),
+            event: HillEvents::SpawnAnts { count: 1 },
+        });
     }
 }
 
 fn hill_events(
     mut commands: Commands,
     ant_handles: Res<AntHandles>,
-    mut hill: ResMut<AntHill>,
-    mut events: EventReader<HillEvents>,
+    mut hills: Query<(&mut AntHill, &Transform, &Colony)>,
+    mut events: EventReader<HillEvent>,
     time: Res<Time>,
     mut data: ResMut<GraphData>,
+    food_kinds: Res<FoodKinds>,
 ) {
-    for event in events.iter() {
+    for HillEvent {
+        hill: entity,
+        event,
+    } in events.iter()
+    {
+        let (mut hill, hill_transform, colony) = if let Ok(hill) = hills.get_mut(*entity) {
+            hill
+        } else {
+            continue;
+        };
         match event {
             HillEvents::SpawnAnts { count } => {
-                data.total_ants += count;
-                let mut rn = rand::thread_rng();
+                if *colony == Colony::PLAYER {
+                    data.total_ants += count;
+                }
                 for _ in 0..*count {
-                    commands
-                        .spawn_bundle((Transform::identity(), GlobalTransform::default()))
-                        .with_children(|creature| {
-                            creature
-                                .spawn_bundle(bevy::pbr::PbrBundle {
-                                    mesh: ant_handles.body_mesh.clone_weak(),
-                                    material: ant_handles.body_color.clone_weak(),
-                                    transform: Transform::from_rotation(Quat::from_rotation_x(
-                                        FRAC_PI_2,
-                                    )),
-                                    ..Default::default()
-                                })
-                                .insert(bevy::pbr::NotShadowCaster);
-                            creature
-                                .spawn_bundle(bevy::pbr::PbrBundle {
-                                    mesh: ant_handles.eye_mesh.clone_weak(),
-                                    material: ant_handles.eye_color.clone_weak(),
-                                    transform: Transform::from_xyz(0.0075, 0.0075, 0.01875),
-                                    ..Default::default()
-                                })
-                                .insert(bevy::pbr::NotShadowCaster);
-                            creature
-                                .spawn_bundle(bevy::pbr::PbrBundle {
-                                    mesh: ant_handles.eye_mesh.clone_weak(),
-                                    material: ant_handles.eye_color.clone_weak(),
-                                    transform: Transform::from_xyz(-0.0075, 0.0075, 0.01875),
-                                    ..Default::default()
-                                })
-                                .insert(bevy::pbr::NotShadowCaster);
-                        })
-                        .insert(Creature {
-                            velocity: Vec3::ZERO,
-                            desired_direction: Vec3::ZERO,
-                            wander_strength: hill.gene.wander_strength,
-                            state: AntState::Wander,
-                            birth: time.seconds_since_startup(),
-                            gene: CreatureGene {
-                                life_expectancy: hill.gene.life_expectancy
-                                    + rn.gen_range(
-                                        -mutations::LIFE_EXPECTANCY..mutations::LIFE_EXPECTANCY,
-                                    ) / 2.0,
-                                max_speed: hill.gene.max_speed
-                                    + rn.gen_range(-mutations::MAX_SPEED..mutations::MAX_SPEED)
-                                        / 2.0,
-                                wander_strength: hill.gene.wander_strength
-                                    + rn.gen_range(
-                                        -mutations::WANDER_STRENGTH..mutations::WANDER_STRENGTH,
-                                    ) / 2.0,
-                                antennas: hill.gene.antennas
-                                    + rn.gen_range(-mutations::ANTENNAS..mutations::ANTENNAS) / 2.0,
-                            },
-                        });
+                    spawn_creature(
+                        &mut commands,
+                        &*ant_handles,
+                        hill_transform.translation,
+                        *entity,
+                        *colony,
+                        mutate(&hill.gene),
+                        time.seconds_since_startup(),
+                    );
                 }
             }
-            HillEvents::RemoveQueenFood(consumed) => hill.queen_food -= consumed,
+            HillEvents::RemoveQueenFood(consumed) => match hill.queen_food.checked_sub(*consumed) {
+                Some(left) => hill.queen_food = left,
+                None => warn!(
+                    "hill {:?} can't spare {} queen food, it has {}",
+                    entity, consumed, hill.queen_food
+                ),
+            },
             HillEvents::ImproveMaxSpeed(boost) => {
                 hill.gene.max_speed = (hill.gene.max_speed + boost).max(0.15)
             }
@@ -203,19 +445,200 @@ fn hill_events(
             HillEvents::ImproveWave(boost) => hill.spawn_per_wave += boost,
             HillEvents::ImproveMutation(boost) => hill.mutation_improvement += boost,
             HillEvents::ReplenishFood(count, ratio, gene) => {
-                for _ in 0..*count {
-                    if rand::thread_rng().gen_bool(*ratio) {
-                        hill.queen_food += 1;
+                hill.replenish(*count, *ratio);
+                if let Some(gene) = gene {
+                    hill.remember_gatherer(*gene);
+                }
+            }
+            HillEvents::DeliverFood { kind, gene } => {
+                if let Some(food_kind) = food_kinds.find(kind).map(|kind| food_kinds.get(kind)) {
+                    *hill.delivered.entry(kind.clone()).or_insert(0) += 1;
+                    if food_kind.nutrition >= 0 {
+                        hill.replenish(food_kind.nutrition as u32, food_kind.queen_food_yield);
                     } else {
-                        hill.food += 1;
+                        hill.food = hill.food.saturating_sub(-food_kind.nutrition as u32);
                     }
                 }
                 if let Some(gene) = gene {
-                    hill.gatherer_genes.push_back(*gene);
-                    if hill.gatherer_genes.len() > 100 {
-                        hill.gatherer_genes.pop_front();
+                    hill.remember_gatherer(*gene);
+                }
+            }
+            HillEvents::FoundNest { .. } => (),
+        }
+    }
+}
+
+fn spawn_creature(
+    commands: &mut Commands,
+    ant_handles: &AntHandles,
+    position: Vec3,
+    home: Entity,
+    colony: Colony,
+    gene: CreatureGene,
+    birth: f64,
+) {
+    let body_color = if colony == Colony::PLAYER {
+        &ant_handles.body_color
+    } else {
+        &ant_handles.rival_body_color
+    };
+    commands
+        .spawn_bundle((
+            Transform::from_xyz(position.x, 0.0, position.z),
+            GlobalTransform::default(),
+        ))
+        .with_children(|creature| {
+            creature
+                .spawn_bundle(bevy::pbr::PbrBundle {
+                    mesh: ant_handles.body_mesh.clone_weak(),
+                    material: body_color.clone_weak(),
+                    transform: Transform::from_rotation(Quat::from_rotation_x(FRAC_PI_2)),
+                    ..Default::default()
+                })
+                .insert(bevy::pbr::NotShadowCaster);
+            creature
+                .spawn_bundle(bevy::pbr::PbrBundle {
+                    mesh: ant_handles.eye_mesh.clone_weak(),
+                    material: ant_handles.eye_color.clone_weak(),
+                    transform: Transform::from_xyz(0.0075, 0.0075, 0.01875),
+                    ..Default::default()
+                })
+                .insert(bevy::pbr::NotShadowCaster);
+            creature
+                .spawn_bundle(bevy::pbr::PbrBundle {
+                    mesh: ant_handles.eye_mesh.clone_weak(),
+                    material: ant_handles.eye_color.clone_weak(),
+                    transform: Transform::from_xyz(-0.0075, 0.0075, 0.01875),
+                    ..Default::default()
+                })
+                .insert(bevy::pbr::NotShadowCaster);
+        })
+        .insert(Creature {
+            velocity: Vec3::ZERO,
+            desired_direction: Vec3::ZERO,
+            wander_strength: gene.wander_strength,
+            state: AntState::Wander,
+            birth,
+            home,
+            colony,
+            gene,
+        })
+        .insert_bundle((
+            Energy::default(),
+            Health::new(ANT_HEALTH),
+            Attack::of_ant(&gene),
+        ));
+}
+
+fn mutate(gene: &CreatureGene) -> CreatureGene {
+    let mut rn = rand::thread_rng();
+    CreatureGene {
+        life_expectancy: gene.life_expectancy
+            + rn.gen_range(-mutations::LIFE_EXPECTANCY..mutations::LIFE_EXPECTANCY) / 2.0,
+        max_speed: gene.max_speed + rn.gen_range(-mutations::MAX_SPEED..mutations::MAX_SPEED) / 2.0,
+        wander_strength: gene.wander_strength
+            + rn.gen_range(-mutations::WANDER_STRENGTH..mutations::WANDER_STRENGTH) / 2.0,
+        antennas: gene.antennas + rn.gen_range(-mutations::ANTENNAS..mutations::ANTENNAS) / 2.0,
+    }
+}
+
+fn send_settlers(
+    mut commands: Commands,
+    mut events: EventReader<HillEvent>,
+    hills: Query<(&AntHill, &Transform, &Colony)>,
+    food_heaps: Query<(&Transform, &FoodHeap)>,
+    mut ants: Query<&mut Creature>,
+) {
+    for HillEvent {
+        hill: entity,
+        event,
+    } in events.iter()
+    {
+        if let HillEvents::FoundNest { count } = event {
+            let (hill, hill_transform, colony) = if let Ok(hill) = hills.get(*entity) {
+                hill
+            } else {
+                continue;
+            };
+            let far_enough = |position: Vec3| {
+                hills.iter().all(|(_, other, _)| {
+                    other.translation.distance_squared(position) > NEST_DISTANCE.powf(2.0)
+                })
+            };
+            // the richest heap far enough from all hills, or a random spot if there are none
+            let position = food_heaps
+                .iter()
+                .filter(|(heap, _)| far_enough(heap.translation))
+                .max_by_key(|(_, heap)| heap.count)
+                .map(|(heap, _)| heap.translation)
+                .unwrap_or_else(|| {
+                    let direction =
+                        Quat::from_rotation_y(rand::thread_rng().gen_range(0.0..(2.0 * PI)))
+                            .mul_vec3(Vec3::X);
+                    (hill_transform.translation + direction * NEST_DISTANCE)
+                        .clamp(Vec3::splat(-BORDER), Vec3::splat(BORDER))
+                });
+            info!("founding a new nest at {:?}", position);
+            let site = commands
+                .spawn_bundle((
+                    Transform::from_xyz(position.x, -0.02, position.z),
+                    GlobalTransform::default(),
+                    NestSite {
+                        gene: hill.gene,
+                        colony: *colony,
+                    },
+                ))
+                .id();
+            ants.iter_mut()
+                .filter(|ant| ant.home == *entity && ant.state == AntState::Wander)
+                .take(*count as usize)
+                .for_each(|mut ant| ant.state = AntState::Settle(position, site));
+        }
+    }
+}
+
+const NEST_DISTANCE: f32 = 1.0;
+
+fn settle_nests(
+    mut commands: Commands,
+    mut ants: Query<(&Transform, &mut Creature)>,
+    sites: Query<&NestSite>,
+    ant_hill_handles: Res<AntHillHandles>,
+) {
+    let mut founded = HashSet::default();
+    for (transform, mut ant) in ants.iter_mut() {
+        if let AntState::Settle(position, site) = ant.state {
+            if transform.translation.distance_squared(position) < (1.0 / DEF).powf(2.0) {
+                if let Ok(nest_site) = sites.get(site) {
+                    if founded.insert(site) {
+                        commands
+                            .entity(site)
+                            .insert_bundle(bevy::pbr::PbrBundle {
+                                mesh: ant_hill_handles.mesh.clone_weak(),
+                                material: if nest_site.colony == Colony::PLAYER {
+                                    ant_hill_handles.color.clone_weak()
+                                } else {
+                                    ant_hill_handles.rival_color.clone_weak()
+                                },
+                                transform: Transform {
+                                    translation: Vec3::new(position.x, -0.02, position.z),
+                                    scale: Vec3::splat(0.6),
+                                    ..Default::default()
+                                },
+                                ..Default::default()
+                            })
+                            .insert(AntHill {
+                                food: 0,
+                                queen_food: 0,
+                                gene: nest_site.gene,
+                                ..Default::default()
+                            })
+                            .insert(nest_site.colony)
+                            .remove::<NestSite>();
                     }
                 }
+                ant.home = site;
+                ant.state = AntState::Wander;
             }
         }
     }
@@ -230,8 +653,11 @@ mod mutations {
 
 pub struct EvolveTimer(pub Timer);
 
-fn evolve_hills(mut hill: ResMut<AntHill>, time: Res<Time>, mut timer: ResMut<EvolveTimer>) {
-    if timer.0.tick(time.delta()).just_finished() {
+fn evolve_hills(mut hills: Query<&mut AntHill>, time: Res<Time>, mut timer: ResMut<EvolveTimer>) {
+    if !timer.0.tick(time.delta()).just_finished() {
+        return;
+    }
+    for mut hill in hills.iter_mut() {
         let mean_gene =
             hill.gatherer_genes
                 .iter()
@@ -1,16 +1,31 @@
-use std::{f32::consts::PI, ops::Deref};
+use std::f32::consts::PI;
 
 use bevy::prelude::*;
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    ant_hill::HillEvents,
-    food::{FoodHeap, FoodPellet},
+    ant_hill::{AntHill, Colony, HillEvent, HillEvents},
+    build_tools::RallyMarker,
+    combat::Health,
+    food::{FoodHandles, FoodHeap, FoodKinds, WorldEvents},
     game_state::GameState,
+    structures::{Structure, StructureKind, BUILD_RANGE, DEPOT_RANGE, WATCH_BOOST, WATCH_RANGE},
     terrain_spawner::{EmptyLot, ObstacleMap},
     DEF,
 };
 
+/// Wandering ants closer than this to a rally marker gather around it
+const RALLY_RANGE: f32 = 1.0;
+/// Of the body, checked against obstacles
+pub const ANT_RADIUS: f32 = 0.015;
+/// Ants caught in an obstacle are moved to free ground up to this far
+const UNSTUCK_RANGE: f32 = 0.2;
+/// Seconds an ant carries material to a blueprint before dropping it and its reservation
+const BUILD_PATIENCE: f64 = 30.0;
+/// Ants only carry material to blueprints this close to their hill
+const BUILD_REACH: f32 = 1.5;
+
 pub struct AntsPlugin;
 
 impl Plugin for AntsPlugin {
@@ -28,8 +43,12 @@ impl Plugin for AntsPlugin {
 pub struct AntHandles {
     pub body_mesh: Handle<bevy::render::mesh::Mesh>,
     pub body_color: Handle<bevy::pbr::StandardMaterial>,
+    pub rival_body_color: Handle<bevy::pbr::StandardMaterial>,
     pub eye_mesh: Handle<bevy::render::mesh::Mesh>,
     pub eye_color: Handle<bevy::pbr::StandardMaterial>,
+    /// Material carried to structures
+    grain_mesh: Handle<bevy::render::mesh::Mesh>,
+    grain_color: Handle<bevy::pbr::StandardMaterial>,
 }
 
 impl FromWorld for AntHandles {
@@ -39,7 +58,7 @@ impl FromWorld for AntHandles {
             .unwrap();
         let body_mesh = meshes.add(bevy::render::mesh::Mesh::from(
             bevy::render::mesh::shape::Capsule {
-                radius: 0.015,
+                radius: ANT_RADIUS,
                 depth: 0.015,
                 latitudes: 4,
                 longitudes: 8,
@@ -53,6 +72,10 @@ impl FromWorld for AntHandles {
             },
         ));
 
+        let grain_mesh = meshes.add(bevy::render::mesh::Mesh::from(
+            bevy::render::mesh::shape::Cube { size: 0.012 },
+        ));
+
         let mut materials = world
             .get_resource_mut::<Assets<bevy::pbr::StandardMaterial>>()
             .unwrap();
@@ -62,21 +85,40 @@ impl FromWorld for AntHandles {
             metallic: 0.0,
             ..Default::default()
         });
+        let rival_body_color = materials.add(bevy::pbr::StandardMaterial {
+            base_color: bevy::render::color::Color::rgb(0.55, 0.15, 0.1),
+            perceptual_roughness: 1.0,
+            metallic: 0.0,
+            ..Default::default()
+        });
         let eye_color = materials.add(bevy::render::color::Color::YELLOW.into());
+        let grain_color = materials.add(bevy::pbr::StandardMaterial {
+            base_color: bevy::render::color::Color::rgb(0.5, 0.4, 0.3),
+            perceptual_roughness: 1.0,
+            metallic: 0.0,
+            ..Default::default()
+        });
 
         Self {
             body_mesh,
             body_color,
+            rival_body_color,
             eye_mesh,
             eye_color,
+            grain_mesh,
+            grain_color,
         }
     }
 }
 
 pub enum AntState {
     Wander,
+    /// Going to a food heap where a pellet is reserved
     PickFood(Vec3, Entity),
-    HasFood,
+    HasFood(usize),
+    Settle(Vec3, Entity),
+    /// Carrying material to a structure where a load is reserved, since a time in seconds
+    Build(Vec3, Entity, f64),
 }
 
 impl PartialEq for AntState {
@@ -92,10 +134,12 @@ pub struct Creature {
     pub wander_strength: f32,
     pub state: AntState,
     pub birth: f64,
+    pub home: Entity,
+    pub colony: Colony,
     pub gene: CreatureGene,
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
 pub struct CreatureGene {
     pub life_expectancy: f64,
     pub max_speed: f32,
@@ -104,92 +148,253 @@ pub struct CreatureGene {
 }
 
 #[derive(Component)]
-struct PickedFood;
+pub struct Energy(pub f32);
+
+impl Default for Energy {
+    fn default() -> Self {
+        Energy(energy::MAX)
+    }
+}
 
+mod energy {
+    pub const MAX: f32 = 1.0;
+    /// Spent per distance travelled, multiplied by the ant max speed
+    pub const DRAIN: f32 = 0.8;
+    /// Wandering ants go back home to eat under this
+    pub const HUNGRY: f32 = 0.35;
+    /// Ants eat at the hill when passing by under this
+    pub const REFUEL: f32 = 0.75;
+    /// Ants carrying food eat it rather than starve under this
+    pub const EMERGENCY: f32 = 0.1;
+    pub const PELLET: f32 = 0.4;
+    /// Gained per second drinking at the water's edge
+    pub const DRINK: f32 = 0.5;
+}
+
+/// Food or material carried by an ant
+#[derive(Component)]
+struct Carried;
+
+#[allow(clippy::too_many_arguments)]
 fn update_ant_state(
     mut commands: Commands,
-    mut ants: Query<(&Transform, &mut Creature, Entity, &Children)>,
-    food_heaps: Query<(&Transform, &Children), (With<FoodHeap>, Without<Creature>)>,
-    mut foods: Query<(&GlobalTransform, &mut FoodPellet), (Without<Creature>, Without<FoodHeap>)>,
-    picked_foods: Query<Entity, With<PickedFood>>,
-    mut hill_events: EventWriter<HillEvents>,
+    mut ants: Query<(&Transform, &mut Creature, &mut Energy, Entity, &Children)>,
+    mut food_heaps: Query<(Entity, &Transform, &mut FoodHeap), Without<Creature>>,
+    carried: Query<Entity, With<Carried>>,
+    mut hills: Query<(&Transform, &mut AntHill), Without<Creature>>,
+    mut hill_events: EventWriter<HillEvent>,
+    food_kinds: Res<FoodKinds>,
+    food_handles: Res<FoodHandles>,
+    ant_handles: Res<AntHandles>,
+    mut structures: Query<(Entity, &Transform, &mut Structure, &Colony), Without<Creature>>,
+    time: Res<Time>,
 ) {
-    for (transform, mut ant, entity, children) in ants.iter_mut() {
-        let mut near = 10.0;
-        let mut target_heap = None;
+    for (transform, mut ant, mut energy, entity, children) in ants.iter_mut() {
+        let near_structure = |kind: StructureKind, range: f32| {
+            structures
+                .iter()
+                .any(|(_, structure_transform, structure, colony)| {
+                    *colony == ant.colony
+                        && structure.is_built(kind)
+                        && Vec2::new(
+                            structure_transform.translation.x - transform.translation.x,
+                            structure_transform.translation.z - transform.translation.z,
+                        )
+                        .length_squared()
+                            < range.powf(2.0)
+                })
+        };
+        let near_watch_post = near_structure(StructureKind::WatchPost, WATCH_RANGE);
+        let near_depot = near_structure(StructureKind::Depot, DEPOT_RANGE);
+        let drop_carried = |commands: &mut Commands| {
+            for child in children.iter() {
+                if carried.get(*child).is_ok() {
+                    commands.entity(*child).despawn_recursive();
+                }
+            }
+        };
+        let home = if let Ok((home, mut hill)) = hills.get_mut(ant.home) {
+            let at_home =
+                transform.translation.distance_squared(home.translation) < (1.0 / DEF).powf(2.0);
+            // eat when passing by home
+            if at_home && energy.0 < energy::REFUEL && hill.food > 0 {
+                hill.food -= 1;
+                energy.0 = energy::MAX;
+            }
+            Some(at_home)
+        } else {
+            None
+        };
         match ant.state {
             AntState::Wander => {
-                // search for food nearby
-                for (food_heap, children) in food_heaps.iter() {
-                    let distance = food_heap
+                // carry material from home to a blueprint, a load of food from its stock
+                let stocked = hills.get(ant.home).map_or(false, |(_, hill)| hill.food > 0);
+                if home == Some(true) && stocked && energy.0 > energy::HUNGRY {
+                    let blueprint = structures
+                        .iter_mut()
+                        .filter(|(_, _, structure, colony)| {
+                            **colony == ant.colony
+                                && Vec2::new(
+                                    structure.site.x - transform.translation.x,
+                                    structure.site.z - transform.translation.z,
+                                )
+                                .length_squared()
+                                    < BUILD_REACH.powf(2.0)
+                        })
+                        .find_map(|(structure_entity, _, mut structure, _)| {
+                            // aim at the site on the ground
+                            let target = Vec3::new(
+                                structure.site.x,
+                                transform.translation.y,
+                                structure.site.z,
+                            );
+                            structure.reserve().then(|| (target, structure_entity))
+                        });
+                    if let Some((target, structure_entity)) = blueprint {
+                        if let Ok((_, mut hill)) = hills.get_mut(ant.home) {
+                            hill.food -= 1;
+                        }
+                        ant.state =
+                            AntState::Build(target, structure_entity, time.seconds_since_startup());
+                        commands.entity(entity).with_children(|ant| {
+                            ant.spawn_bundle(bevy::pbr::PbrBundle {
+                                mesh: ant_handles.grain_mesh.clone_weak(),
+                                material: ant_handles.grain_color.clone_weak(),
+                                transform: Transform::from_xyz(0.0, 0.01, 0.02),
+                                ..Default::default()
+                            })
+                            .insert_bundle((Carried, bevy::pbr::NotShadowCaster));
+                        });
+                        continue;
+                    }
+                }
+                // search for food nearby, further around watch posts
+                let antennas = if near_watch_post {
+                    ant.gene.antennas * WATCH_BOOST
+                } else {
+                    ant.gene.antennas
+                };
+                let mut near = (1.0 / DEF * antennas).powf(2.0);
+                let mut target_heap = None;
+                for (heap_entity, heap_transform, heap) in food_heaps.iter() {
+                    let distance = heap_transform
                         .translation
                         .distance_squared(transform.translation);
-                    if distance < near {
+                    if distance < near && heap.available() > 0 {
                         near = distance;
-                        target_heap = Some(children);
+                        target_heap = Some(heap_entity);
                     }
                 }
-                if near < (1.0 / DEF * ant.gene.antennas).powf(2.0) {
-                    for food_entity in Deref::deref(target_heap.unwrap()) {
-                        if let Ok((food, mut pellet)) = foods.get_mut(*food_entity) {
-                            if !pellet.targeted {
-                                pellet.targeted = true;
-                                ant.state = AntState::PickFood(food.translation, *food_entity);
-                                break;
-                            }
-                        }
+                if let Some(heap_entity) = target_heap {
+                    let (_, heap_transform, mut heap) = food_heaps.get_mut(heap_entity).unwrap();
+                    if heap.reserve() {
+                        ant.state = AntState::PickFood(heap_transform.translation, heap_entity);
                     }
                 }
             }
-            AntState::PickFood(target, food_entity) => {
+            AntState::PickFood(target, heap_entity) => {
                 // pick food if close enough
                 if transform.translation.distance_squared(target) < (1.0 / DEF).powf(2.0) {
-                    ant.state = AntState::HasFood;
-                    if foods.get_mut(food_entity).is_ok() {
-                        commands
-                            .entity(food_entity)
-                            .insert_bundle((
-                                Parent(entity),
-                                Transform {
+                    let picked = food_heaps
+                        .get_mut(heap_entity)
+                        .ok()
+                        .and_then(|(_, _, mut heap)| heap.take().then(|| heap.kind));
+                    if let Some(kind) = picked {
+                        ant.state = AntState::HasFood(kind);
+                        commands.entity(entity).with_children(|ant| {
+                            ant.spawn_bundle(bevy::pbr::PbrBundle {
+                                mesh: food_handles.mesh.clone_weak(),
+                                material: food_handles.colors[kind].clone_weak(),
+                                transform: Transform {
                                     translation: Vec3::new(0.0, 0.01, 0.02),
                                     scale: Vec3::splat(0.8),
                                     rotation: Default::default(),
                                 },
-                                PickedFood,
-                            ))
-                            .remove::<FoodPellet>();
+                                ..Default::default()
+                            })
+                            .insert_bundle((Carried, bevy::pbr::NotShadowCaster));
+                        });
                     } else {
                         ant.state = AntState::Wander;
                     }
                 }
             }
-            AntState::HasFood => {
-                // drop food at home if close enough
-                if transform.translation.distance_squared(Vec3::ZERO) < (1.0 / DEF).powf(2.0) {
-                    hill_events.send(HillEvents::ReplenishFood(1, 0.1, Some(ant.gene)));
+            AntState::HasFood(kind) => {
+                let at_home = if let Some(at_home) = home {
+                    at_home
+                } else {
+                    continue;
+                };
+                // drop food at home or at a depot if close enough
+                let at_home = at_home || near_depot;
+                if at_home {
+                    hill_events.send(HillEvent {
+                        hill: ant.home,
+                        event: HillEvents::DeliverFood {
+                            kind: food_kinds.get(kind).id.clone(),
+                            gene: Some(ant.gene),
+                        },
+                    });
+                }
+                // or eat it rather than starve on the way
+                let starving = !at_home && energy.0 < energy::EMERGENCY;
+                if starving {
+                    energy.0 = (energy.0 + energy::PELLET * food_kinds.get(kind).nutrition as f32)
+                        .min(energy::MAX);
+                }
+                if at_home || starving {
                     ant.state = AntState::Wander;
-                    for child in children.iter() {
-                        if picked_foods.get(*child).is_ok() {
-                            commands.entity(*child).despawn_recursive();
-                        }
+                    drop_carried(&mut commands);
+                }
+            }
+            AntState::Build(target, structure_entity, since) => {
+                if let Ok((_, _, mut structure, _)) = structures.get_mut(structure_entity) {
+                    if transform.translation.distance_squared(target) < BUILD_RANGE.powf(2.0) {
+                        structure.deliver();
+                    } else if time.seconds_since_startup() - since > BUILD_PATIENCE {
+                        // the site is out of reach, let another ant try
+                        structure.release();
+                    } else {
+                        continue;
                     }
                 }
+                ant.state = AntState::Wander;
+                drop_carried(&mut commands);
             }
+            AntState::Settle(_, _) => (),
         }
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn move_ants(
     mut commands: Commands,
-    mut ants: Query<(&mut Transform, &mut Creature)>,
+    mut ants: Query<(&mut Transform, &mut Creature, &mut Energy)>,
+    hills: Query<&Transform, (With<AntHill>, Without<Creature>)>,
     time: Res<Time>,
     obstacle_map: Res<ObstacleMap>,
+    food_kinds: Res<FoodKinds>,
+    rally_markers: Query<&Transform, (With<RallyMarker>, Without<Creature>)>,
+    structures: Query<(&Transform, &Structure, &Colony), Without<Creature>>,
+    mut world_events: EventWriter<WorldEvents>,
 ) {
     let steer_strength = 2.0;
-    for (mut transform, mut ant) in ants.iter_mut() {
+    for (mut transform, mut ant, mut energy) in ants.iter_mut() {
+        let rally = rally_markers
+            .iter()
+            .map(|marker| Vec3::new(marker.translation.x, 0.0, marker.translation.z))
+            .find(|marker| marker.distance_squared(transform.translation) < RALLY_RANGE.powf(2.0));
         // find where we want to go
         let moving_towards = match ant.state {
-            AntState::Wander => {
+            AntState::Wander
+                if energy.0 > energy::HUNGRY && ant.colony == Colony::PLAYER && rally.is_some() =>
+            {
+                (transform.translation - rally.unwrap()).normalize_or_zero() / 2.0
+                    + Quat::from_rotation_y(rand::thread_rng().gen_range(0.0..(2.0 * PI)))
+                        .mul_vec3(Vec3::X)
+                        * ant.wander_strength
+            }
+            AntState::Wander if energy.0 > energy::HUNGRY => {
                 // TODO: look for pheromons
                 Quat::from_rotation_y(rand::thread_rng().gen_range(0.0..(2.0 * PI)))
                     .mul_vec3(Vec3::X)
@@ -202,9 +407,40 @@ fn move_ants(
                         * ant.wander_strength
                         / 2.0
             }
-            AntState::HasFood => {
+            AntState::Settle(position, _) | AntState::Build(position, _, _) => {
+                (transform.translation - position).normalize_or_zero()
+                    + Quat::from_rotation_y(rand::thread_rng().gen_range(0.0..(2.0 * PI)))
+                        .mul_vec3(Vec3::X)
+                        * ant.wander_strength
+                        / 2.0
+            }
+            AntState::Wander | AntState::HasFood(_) => {
                 // TODO: look for pheromons
-                transform.translation.normalize()
+                let home = hills
+                    .get(ant.home)
+                    .map(|home| Vec3::new(home.translation.x, 0.0, home.translation.z))
+                    .unwrap_or(Vec3::ZERO);
+                // food can also be dropped at the nearest depot
+                let home = if let AntState::HasFood(_) = ant.state {
+                    structures
+                        .iter()
+                        .filter(|(_, structure, colony)| {
+                            **colony == ant.colony && structure.is_built(StructureKind::Depot)
+                        })
+                        .map(|(depot, _, _)| {
+                            Vec3::new(depot.translation.x, 0.0, depot.translation.z)
+                        })
+                        .chain(std::iter::once(home))
+                        .min_by(|a, b| {
+                            a.distance_squared(transform.translation)
+                                .partial_cmp(&b.distance_squared(transform.translation))
+                                .unwrap()
+                        })
+                        .unwrap()
+                } else {
+                    home
+                };
+                (transform.translation - home).normalize_or_zero()
                     + Quat::from_rotation_y(rand::thread_rng().gen_range(0.0..(2.0 * PI)))
                         .mul_vec3(Vec3::X)
                         * ant.wander_strength
@@ -213,12 +449,28 @@ fn move_ants(
         };
         ant.desired_direction = (ant.desired_direction - moving_towards).normalize();
 
-        let desired_velocity = ant.desired_direction * ant.gene.max_speed;
+        // carried food slows ants down
+        let load = if let AntState::HasFood(kind) = ant.state {
+            food_kinds.get(kind).carry_weight
+        } else {
+            1.0
+        };
+        // ants lean towards easier ground ahead of them
+        let heading = obstacle_map.easiest_heading(
+            transform.translation.x,
+            transform.translation.z,
+            ant.desired_direction,
+            2.0 / DEF,
+            ANT_RADIUS,
+        );
+        let max_speed = ant.gene.max_speed / load
+            * obstacle_map.speed_factor(transform.translation.x, transform.translation.z, heading);
+        let desired_velocity = heading * max_speed;
         let desired_steering_force = (desired_velocity - ant.velocity) * steer_strength;
         let acceleration = desired_steering_force.clamp_length_max(steer_strength);
 
-        ant.velocity = (ant.velocity + acceleration * time.delta_seconds())
-            .clamp_length_max(ant.gene.max_speed);
+        ant.velocity =
+            (ant.velocity + acceleration * time.delta_seconds()).clamp_length_max(max_speed);
 
         let angle = if ant.velocity.x < 0.0 {
             -ant.velocity.angle_between(Vec3::new(0.0, 0.0, 1.0))
@@ -227,9 +479,13 @@ fn move_ants(
         };
         let forward = transform.translation + ant.velocity * time.delta_seconds();
         let forward_forward = transform.translation + ant.velocity / DEF * 2.0;
-        if !obstacle_map.is_obstacle(forward_forward.x, forward_forward.z, 0.0) {
+        if !obstacle_map.is_obstacle(forward_forward.x, forward_forward.z, ANT_RADIUS) {
             transform.rotation = Quat::from_rotation_y(angle);
             transform.translation = forward;
+            energy.0 -= (ant.velocity * time.delta_seconds()).length()
+                * ant.gene.max_speed
+                * load
+                * energy::DRAIN;
             ant.wander_strength = ant.gene.wander_strength;
             let position = IVec2::new(
                 transform.translation.x as i32,
@@ -238,23 +494,50 @@ fn move_ants(
             commands.spawn_bundle((EmptyLot::new(position, true),));
         } else {
             ant.wander_strength += 0.5;
+            if obstacle_map.is_water(forward_forward.x, forward_forward.z) {
+                energy.0 = (energy.0 + energy::DRINK * time.delta_seconds()).min(energy::MAX);
+            } else if ant.colony == Colony::PLAYER
+                && obstacle_map.is_diggable(forward_forward.x, forward_forward.z)
+            {
+                world_events.send(WorldEvents::Dig(forward_forward));
+            }
+            // a wall or a pebble was put down on the ant
+            let (x, z) = (transform.translation.x, transform.translation.z);
+            if obstacle_map.is_obstacle(x, z, ANT_RADIUS) {
+                if let Some(free) = obstacle_map.nearest_free(x, z, ANT_RADIUS, UNSTUCK_RANGE) {
+                    transform.translation.x = free.x;
+                    transform.translation.z = free.y;
+                }
+            }
         }
     }
 }
 
 fn aging_ants(
     mut commands: Commands,
-    ants: Query<(Entity, &Creature)>,
-    mut foods: Query<&mut FoodPellet, (Without<Creature>, Without<FoodHeap>)>,
+    ants: Query<(Entity, &Creature, &Energy, &Health)>,
+    mut food_heaps: Query<&mut FoodHeap>,
+    mut structures: Query<&mut Structure>,
     time: Res<Time>,
 ) {
-    for (entity, ant) in ants.iter() {
+    for (entity, ant, energy, health) in ants.iter() {
         // if ant.state == AntState::Wander || ant.state == AntState::HasFood {
-        if time.seconds_since_startup() - ant.birth > ant.gene.life_expectancy {
-            if let AntState::PickFood(_, food_entity) = ant.state {
-                if let Ok(mut food_pellet) = foods.get_mut(food_entity) {
-                    food_pellet.targeted = false;
+        if time.seconds_since_startup() - ant.birth > ant.gene.life_expectancy
+            || energy.0 <= 0.0
+            || health.is_dead()
+        {
+            match ant.state {
+                AntState::PickFood(_, heap_entity) => {
+                    if let Ok(mut heap) = food_heaps.get_mut(heap_entity) {
+                        heap.release();
+                    }
+                }
+                AntState::Build(_, structure_entity, _) => {
+                    if let Ok(mut structure) = structures.get_mut(structure_entity) {
+                        structure.release();
+                    }
                 }
+                _ => (),
             }
             commands.entity(entity).despawn_recursive();
         }
@@ -0,0 +1,137 @@
+use bevy::log::warn;
+use serde::{Deserialize, Serialize};
+
+const BIOMES_FILE: &str = "biomes.conf";
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Biome {
+    pub id: String,
+    pub name: String,
+    /// Range of elevation it covers, from 0.0 (low) to 1.0 (high)
+    pub elevation: (f32, f32),
+    /// Range of moisture it covers, from 0.0 (arid) to 1.0 (wet)
+    pub moisture: (f32, f32),
+    pub color: [f32; 3],
+    pub roughness: f32,
+    /// Creatures can't walk on it when false
+    pub passable: bool,
+    /// Chances for a food heap to be dropped on it, relative to other biomes
+    pub food_weight: f32,
+    /// Creatures move this many times slower on it
+    pub movement_cost: f32,
+}
+
+impl Biome {
+    fn contains(&self, elevation: f32, moisture: f32) -> bool {
+        (self.elevation.0..=self.elevation.1).contains(&elevation)
+            && (self.moisture.0..=self.moisture.1).contains(&moisture)
+    }
+}
+
+/// Biomes by priority: a spot belongs to the first one covering its elevation and moisture
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BiomeTable {
+    pub biomes: Vec<Biome>,
+}
+
+impl BiomeTable {
+    /// Reads the biomes from `BIOMES_FILE`, or the defaults when it holds none
+    pub fn load() -> Self {
+        match config::read_from::<BiomeTable>(BIOMES_FILE) {
+            Ok(biomes) if biomes.biomes.is_empty() => {
+                warn!("no biomes in {}, using defaults", BIOMES_FILE);
+                BiomeTable::default()
+            }
+            Ok(biomes) => biomes,
+            Err(error) => {
+                warn!("could not read {}, using defaults: {}", BIOMES_FILE, error);
+                BiomeTable::default()
+            }
+        }
+    }
+
+    pub fn get(&self, biome: usize) -> &Biome {
+        &self.biomes[biome]
+    }
+
+    /// The biome of a spot, the last one when none covers it
+    pub fn classify(&self, elevation: f32, moisture: f32) -> usize {
+        let (elevation, moisture) = (elevation.clamp(0.0, 1.0), moisture.clamp(0.0, 1.0));
+        self.biomes
+            .iter()
+            .position(|biome| biome.contains(elevation, moisture))
+            .unwrap_or(self.biomes.len() - 1)
+    }
+
+    pub fn max_food_weight(&self) -> f32 {
+        self.biomes
+            .iter()
+            .map(|biome| biome.food_weight)
+            .fold(0.0, f32::max)
+    }
+}
+
+impl Default for BiomeTable {
+    fn default() -> Self {
+        let biome = |id: &str,
+                     name: &str,
+                     (elevation, moisture): ((f32, f32), (f32, f32)),
+                     color: [f32; 3],
+                     roughness: f32,
+                     (food_weight, movement_cost): (f32, f32)| Biome {
+            id: id.to_string(),
+            name: name.to_string(),
+            elevation,
+            moisture,
+            color,
+            roughness,
+            passable: true,
+            food_weight,
+            movement_cost,
+        };
+        BiomeTable {
+            biomes: vec![
+                biome(
+                    "rock",
+                    "Rock",
+                    ((0.8, 1.0), (0.0, 1.0)),
+                    [0.62, 0.6, 0.58],
+                    0.9,
+                    (0.2, 1.3),
+                ),
+                biome(
+                    "marsh",
+                    "Marsh",
+                    ((0.0, 0.4), (0.65, 1.0)),
+                    [0.3, 0.42, 0.3],
+                    0.3,
+                    (0.6, 1.4),
+                ),
+                biome(
+                    "sand",
+                    "Sand",
+                    ((0.0, 0.8), (0.0, 0.3)),
+                    [0.85, 0.78, 0.6],
+                    1.0,
+                    (0.3, 0.87),
+                ),
+                biome(
+                    "forest_floor",
+                    "Forest Floor",
+                    ((0.0, 0.8), (0.5, 1.0)),
+                    [0.25, 0.45, 0.2],
+                    0.8,
+                    (1.5, 1.1),
+                ),
+                biome(
+                    "meadow",
+                    "Meadow",
+                    ((0.0, 1.0), (0.0, 1.0)),
+                    [0.45, 0.65, 0.3],
+                    1.0,
+                    (1.0, 1.0),
+                ),
+            ],
+        }
+    }
+}
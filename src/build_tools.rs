@@ -0,0 +1,268 @@
+use std::time::Duration;
+
+use bevy::{pbr::NotShadowCaster, prelude::*};
+use bevy_egui::EguiContext;
+
+use crate::{
+    ant_hill::{AntHill, MainHill},
+    food::WorldEvents,
+    game_state::GameState,
+    picking::TerrainCursor,
+    structures::StructureKind,
+    terrain_spawner::ObstacleMap,
+};
+
+const PEBBLE_RADIUS: f32 = 0.06;
+const RALLY_DURATION: f32 = 20.0;
+
+pub struct BuildToolsPlugin;
+
+impl Plugin for BuildToolsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<BuildHandles>()
+            .init_resource::<SelectedTool>()
+            .add_system_set(SystemSet::on_enter(GameState::Playing).with_system(reset_tools))
+            .add_system_set(
+                SystemSet::on_update(GameState::Playing)
+                    .with_system(use_build_tool)
+                    .with_system(expire_rally_markers),
+            );
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BuildTool {
+    Bait,
+    Pebble,
+    RallyMarker,
+    Wall,
+    Depot,
+    WatchPost,
+    Bridge,
+    Raft,
+}
+
+impl BuildTool {
+    pub const ALL: [BuildTool; 8] = [
+        BuildTool::Bait,
+        BuildTool::Pebble,
+        BuildTool::RallyMarker,
+        BuildTool::Wall,
+        BuildTool::Depot,
+        BuildTool::WatchPost,
+        BuildTool::Bridge,
+        BuildTool::Raft,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BuildTool::Bait => "Bait",
+            BuildTool::Pebble => "Pebble",
+            BuildTool::RallyMarker => "Rally Marker",
+            BuildTool::Wall => "Wall",
+            BuildTool::Depot => "Food Depot",
+            BuildTool::WatchPost => "Watch Post",
+            BuildTool::Bridge => "Bridge",
+            BuildTool::Raft => "Leaf Raft",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            BuildTool::Bait => "Drop a heap of food where you click.",
+            BuildTool::Pebble => "Drop a pebble that ants and anteaters have to walk around.",
+            BuildTool::RallyMarker => "Wandering ants gather around it for a while.",
+            BuildTool::Wall => {
+                "Workers build a wall that blocks the way, facing away from the hill."
+            }
+            BuildTool::Depot => "Workers build a depot where ants can drop the food they carry.",
+            BuildTool::WatchPost => "Workers build a post that helps nearby ants sense food.",
+            BuildTool::Bridge => "Workers build a bridge over water, leading away from the hill.",
+            BuildTool::Raft => "Workers build a short crossing of leaves that rots away.",
+        }
+    }
+
+    /// Queen food spent each time the tool is used, workers also take a unit of the food of
+    /// the hill for each load of material they bring to a blueprint
+    pub fn cost(&self) -> u32 {
+        match self {
+            BuildTool::Bait => 8,
+            BuildTool::Pebble => 3,
+            BuildTool::RallyMarker => 2,
+            BuildTool::Wall => 2,
+            BuildTool::Depot => 5,
+            BuildTool::WatchPost => 4,
+            BuildTool::Bridge => 6,
+            BuildTool::Raft => 2,
+        }
+    }
+
+    /// Structure whose blueprint the tool places
+    fn structure(&self) -> Option<StructureKind> {
+        match self {
+            BuildTool::Wall => Some(StructureKind::Wall),
+            BuildTool::Depot => Some(StructureKind::Depot),
+            BuildTool::WatchPost => Some(StructureKind::WatchPost),
+            BuildTool::Bridge => Some(StructureKind::Bridge),
+            BuildTool::Raft => Some(StructureKind::Raft),
+            _ => None,
+        }
+    }
+}
+
+/// Tool used on the next click on the terrain
+#[derive(Default)]
+pub struct SelectedTool(pub Option<BuildTool>);
+
+#[derive(Component)]
+pub struct RallyMarker(Timer);
+
+#[derive(Component)]
+struct Pebble;
+
+struct BuildHandles {
+    pebble_mesh: Handle<bevy::render::mesh::Mesh>,
+    pebble_color: Handle<bevy::pbr::StandardMaterial>,
+    marker_mesh: Handle<bevy::render::mesh::Mesh>,
+    marker_color: Handle<bevy::pbr::StandardMaterial>,
+}
+
+impl FromWorld for BuildHandles {
+    fn from_world(world: &mut World) -> Self {
+        let mut meshes = world
+            .get_resource_mut::<Assets<bevy::render::mesh::Mesh>>()
+            .unwrap();
+        let pebble_mesh = meshes.add(bevy::render::mesh::Mesh::from(
+            bevy::render::mesh::shape::Icosphere {
+                radius: PEBBLE_RADIUS,
+                subdivisions: 1,
+            },
+        ));
+        let marker_mesh = meshes.add(bevy::render::mesh::Mesh::from(
+            bevy::render::mesh::shape::Capsule {
+                radius: 0.008,
+                depth: 0.2,
+                latitudes: 2,
+                longitudes: 6,
+                ..Default::default()
+            },
+        ));
+
+        let mut materials = world
+            .get_resource_mut::<Assets<bevy::pbr::StandardMaterial>>()
+            .unwrap();
+        let pebble_color = materials.add(bevy::pbr::StandardMaterial {
+            base_color: bevy::render::color::Color::rgb(0.45, 0.42, 0.4),
+            perceptual_roughness: 1.0,
+            metallic: 0.0,
+            ..Default::default()
+        });
+        let marker_color = materials.add(bevy::pbr::StandardMaterial {
+            base_color: bevy::render::color::Color::ORANGE,
+            unlit: true,
+            ..Default::default()
+        });
+
+        Self {
+            pebble_mesh,
+            pebble_color,
+            marker_mesh,
+            marker_color,
+        }
+    }
+}
+
+fn reset_tools(mut selected: ResMut<SelectedTool>, mut obstacle_map: ResMut<ObstacleMap>) {
+    selected.0 = None;
+    obstacle_map.clear_placed();
+}
+
+#[allow(clippy::too_many_arguments)]
+fn use_build_tool(
+    mut commands: Commands,
+    mouse: Res<Input<MouseButton>>,
+    egui_context: Res<EguiContext>,
+    cursor: Res<TerrainCursor>,
+    mut selected: ResMut<SelectedTool>,
+    mut main_hill: Query<&mut AntHill, With<MainHill>>,
+    markers: Query<Entity, With<RallyMarker>>,
+    mut world_events: EventWriter<WorldEvents>,
+    mut obstacle_map: ResMut<ObstacleMap>,
+    build_handles: Res<BuildHandles>,
+) {
+    if mouse.just_pressed(MouseButton::Right) {
+        selected.0 = None;
+    }
+    let tool = if let Some(tool) = selected.0 {
+        tool
+    } else {
+        return;
+    };
+    if !mouse.just_pressed(MouseButton::Left) || egui_context.ctx().wants_pointer_input() {
+        return;
+    }
+    let (position, mut anthill) = match (cursor.0, main_hill.get_single_mut()) {
+        (Some(position), Ok(anthill)) => (position, anthill),
+        _ => return,
+    };
+    // crossings go over water, everything else on dry free ground
+    let misplaced = if tool.structure().map_or(false, |kind| kind.crosses_water()) {
+        !obstacle_map.is_water(position.x, position.z)
+    } else {
+        obstacle_map.is_obstacle(position.x, position.z, 0.0)
+    };
+    if anthill.queen_food < tool.cost() || misplaced {
+        return;
+    }
+    match tool {
+        BuildTool::Bait => world_events.send(WorldEvents::PlaceFood(position)),
+        BuildTool::Pebble => {
+            obstacle_map.place_obstacle(position.x, position.z, PEBBLE_RADIUS);
+            commands
+                .spawn_bundle(bevy::pbr::PbrBundle {
+                    mesh: build_handles.pebble_mesh.clone_weak(),
+                    material: build_handles.pebble_color.clone_weak(),
+                    transform: Transform::from_translation(position)
+                        .with_scale(Vec3::new(1.0, 0.6, 1.0)),
+                    ..Default::default()
+                })
+                .insert(Pebble);
+        }
+        BuildTool::RallyMarker => {
+            // only one marker at a time
+            for marker in markers.iter() {
+                commands.entity(marker).despawn_recursive();
+            }
+            commands
+                .spawn_bundle(bevy::pbr::PbrBundle {
+                    mesh: build_handles.marker_mesh.clone_weak(),
+                    material: build_handles.marker_color.clone_weak(),
+                    transform: Transform::from_translation(position + Vec3::Y * 0.1),
+                    ..Default::default()
+                })
+                .insert_bundle((
+                    RallyMarker(Timer::new(Duration::from_secs_f32(RALLY_DURATION), false)),
+                    NotShadowCaster,
+                ));
+        }
+        _ => {
+            if let Some(kind) = tool.structure() {
+                world_events.send(WorldEvents::PlaceBlueprint(kind, position));
+            }
+        }
+    }
+    anthill.queen_food -= tool.cost();
+    selected.0 = None;
+}
+
+fn expire_rally_markers(
+    mut commands: Commands,
+    mut markers: Query<(Entity, &mut RallyMarker)>,
+    time: Res<Time>,
+) {
+    for (entity, mut marker) in markers.iter_mut() {
+        if marker.0.tick(time.delta()).just_finished() {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
@@ -3,7 +3,11 @@ use std::{collections::hash_map::Entry, f32::consts::FRAC_PI_2};
 use bevy::{pbr::DirectionalLight, prelude::*, utils::HashMap};
 // use bevy_mod_raycast::RayCastSource;
 
-use crate::{game_state::GameState, terrain_spawner::EmptyLot, BORDER};
+use crate::{
+    game_state::GameState,
+    terrain_spawner::{EmptyLot, NoiseSeeds},
+    BORDER,
+};
 
 pub struct CameraPlugin;
 
@@ -27,10 +31,11 @@ impl Plugin for CameraPlugin {
 #[derive(Component)]
 struct CameraParent;
 
-fn setup(mut commands: Commands) {
+fn setup(mut commands: Commands, noise_seeds: Res<NoiseSeeds>) {
+    let hill = noise_seeds.hill();
     commands
         .spawn_bundle((
-            Transform::default(),
+            Transform::from_xyz(hill.x, 0.0, hill.y),
             GlobalTransform::default(),
             CameraParent,
         ))
@@ -71,6 +76,12 @@ fn setup(mut commands: Commands) {
 #[derive(Default)]
 pub struct VisibleLots(HashMap<IVec2, Entity>);
 
+impl VisibleLots {
+    pub fn contains(&self, lot: IVec2) -> bool {
+        self.0.contains_key(&lot)
+    }
+}
+
 fn refresh_visible_lots(
     mut commands: Commands,
     windows: Res<Windows>,
@@ -0,0 +1,128 @@
+use bevy::prelude::*;
+
+use crate::{
+    ant_eaters::AntEater,
+    ants::{Creature, CreatureGene},
+    game_state::GameState,
+    predators::Predator,
+    structures::Structure,
+};
+
+/// Bite of an ant with the starting max speed, per second
+const ANT_BITE: f32 = 0.12;
+const BASE_SPEED: f32 = 0.25;
+pub const ANT_HEALTH: f32 = 1.0;
+pub const ANTEATER_HEALTH: f32 = 40.0;
+/// Ants killed per second by an anteater
+const ANTEATER_BITE: f32 = 4.0;
+/// Squared distance at which anteaters and ants reach each other
+const REACH: f32 = 0.017;
+/// Damage dealt per second by a predator to a structure it stands on
+const SMASH: f32 = 2.0;
+/// Squared distance at which predators damage structures
+const STRUCTURE_REACH: f32 = 0.04;
+
+pub struct CombatPlugin;
+
+impl Plugin for CombatPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(
+            SystemSet::on_update(GameState::Playing)
+                .with_system(ants_bite_predators)
+                .with_system(anteaters_bite_ants)
+                .with_system(predators_smash_structures),
+        );
+    }
+}
+
+#[derive(Component)]
+pub struct Health {
+    pub current: f32,
+    pub max: f32,
+}
+
+impl Health {
+    pub fn new(max: f32) -> Self {
+        Health { current: max, max }
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.current <= 0.0
+    }
+}
+
+/// Damage dealt per second to an enemy in reach
+#[derive(Component)]
+pub struct Attack(pub f32);
+
+impl Attack {
+    /// Faster ants bite harder
+    pub fn of_ant(gene: &CreatureGene) -> Self {
+        Attack(ANT_BITE * (gene.max_speed / BASE_SPEED).max(0.0))
+    }
+
+    pub fn of_anteater() -> Self {
+        Attack(ANTEATER_BITE * ANT_HEALTH)
+    }
+}
+
+/// Every ant in reach of a predator bites it, so a swarm brings it down quickly
+fn ants_bite_predators(
+    mut predators: Query<(&Transform, &mut Health), (Without<Creature>, Without<Structure>)>,
+    ants: Query<(&Transform, &Attack), With<Creature>>,
+    time: Res<Time>,
+) {
+    for (transform, mut health) in predators.iter_mut() {
+        let damage = ants
+            .iter()
+            .filter(|(ant, _)| ant.translation.distance_squared(transform.translation) < REACH)
+            .map(|(_, attack)| attack.0)
+            .sum::<f32>();
+        health.current -= damage * time.delta_seconds();
+    }
+}
+
+/// Anteaters bite the nearest ant in reach, killing a few of them each second
+fn anteaters_bite_ants(
+    mut anteaters: Query<(&Transform, &Attack, &mut AntEater)>,
+    mut ants: Query<(&Transform, &mut Health), (With<Creature>, Without<AntEater>)>,
+    time: Res<Time>,
+) {
+    for (transform, attack, mut anteater) in anteaters.iter_mut() {
+        let nearest = ants
+            .iter_mut()
+            .filter(|(_, health)| !health.is_dead())
+            .map(|(ant, health)| {
+                (
+                    ant.translation.distance_squared(transform.translation),
+                    health,
+                )
+            })
+            .filter(|(distance, _)| *distance < REACH)
+            .min_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap());
+        if let Some((_, mut health)) = nearest {
+            health.current -= attack.0 * time.delta_seconds();
+            if health.is_dead() {
+                anteater.ant_killed += 1;
+            }
+        }
+    }
+}
+
+/// Anteaters and predators wreck the structures they walk over
+fn predators_smash_structures(
+    predators: Query<&Transform, Or<(With<AntEater>, With<Predator>)>>,
+    mut structures: Query<(&Transform, &mut Health), With<Structure>>,
+    time: Res<Time>,
+) {
+    for (transform, mut health) in structures.iter_mut() {
+        let smashing = predators
+            .iter()
+            .filter(|predator| {
+                let offset = predator.translation - transform.translation;
+                Vec2::new(offset.x, offset.z).length_squared() < STRUCTURE_REACH
+            })
+            .count();
+        health.current -= SMASH * smashing as f32 * time.delta_seconds();
+    }
+}
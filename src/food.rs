@@ -2,20 +2,33 @@ use std::{f32::consts::PI, iter, time::Duration};
 
 use bevy::{pbr::NotShadowCaster, prelude::*};
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 
-use crate::{game_state::GameState, terrain_spawner::ObstacleMap, BORDER, DEF};
+use crate::{
+    ant_hill::AntHill,
+    biomes::BiomeTable,
+    food_placement::{FoodPlacer, Placement},
+    game_state::{Difficulty, GameState},
+    structures::StructureKind,
+    terrain_spawner::{NoiseSeeds, ObstacleMap},
+    BORDER, DEF,
+};
+
+const FOOD_KINDS_FILE: &str = "food.conf";
 
 pub struct FoodPlugin;
 
 impl Plugin for FoodPlugin {
     fn build(&self, app: &mut App) {
-        app.init_resource::<FoodHandles>()
+        app.insert_resource(FoodKinds::load())
+            .init_resource::<FoodHandles>()
             .add_event::<WorldEvents>()
             .add_system_to_stage(CoreStage::PostUpdate, remove_empty_heaps)
             .add_system_set(
                 SystemSet::on_update(GameState::Playing)
                     .with_system(spawn_food)
                     .with_system(food_gone_bad)
+                    .with_system(shrink_heaps)
                     .with_system(enter_the_anteater)
                     .with_system(pop_food)
                     .with_system(faster_decay.config(|(_, timer, _)| {
@@ -25,9 +38,194 @@ impl Plugin for FoodPlugin {
     }
 }
 
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum SpawnBiome {
+    Anywhere,
+    Dry,
+    Wet,
+    Nowhere,
+}
+
+impl SpawnBiome {
+    fn accepts(&self, moisture: f32) -> bool {
+        match self {
+            SpawnBiome::Anywhere => true,
+            SpawnBiome::Dry => moisture < 0.5,
+            SpawnBiome::Wet => moisture >= 0.5,
+            SpawnBiome::Nowhere => false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FoodKind {
+    pub id: String,
+    pub name: String,
+    pub color: [f32; 3],
+    /// Ants carrying it move this many times slower
+    pub carry_weight: f32,
+    /// Hill food per pellet, toxic food removes some
+    pub nutrition: i32,
+    /// Chance for each unit of nutrition to go to the queen instead
+    pub queen_food_yield: f64,
+    pub spawn_biome: SpawnBiome,
+    pub spawn_weight: f32,
+}
+
+impl FoodKind {
+    /// Lightest load an ant can carry, so that carrying never makes it infinitely fast
+    const MIN_CARRY_WEIGHT: f32 = 0.1;
+
+    fn validated(mut self) -> Self {
+        if self.carry_weight.is_nan() || self.carry_weight < Self::MIN_CARRY_WEIGHT {
+            warn!(
+                "carry_weight of {} must be at least {}, not {}",
+                self.id,
+                Self::MIN_CARRY_WEIGHT,
+                self.carry_weight
+            );
+            self.carry_weight = Self::MIN_CARRY_WEIGHT;
+        }
+        if !(0.0..=1.0).contains(&self.queen_food_yield) {
+            warn!(
+                "queen_food_yield of {} must be between 0 and 1, not {}",
+                self.id, self.queen_food_yield
+            );
+            self.queen_food_yield = if self.queen_food_yield > 1.0 {
+                1.0
+            } else {
+                0.0
+            };
+        }
+        self
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FoodKinds {
+    pub kinds: Vec<FoodKind>,
+}
+
+impl FoodKinds {
+    /// Reads the kinds from `FOOD_KINDS_FILE`, fixing values that would break the game, or the
+    /// defaults when it holds none
+    fn load() -> Self {
+        let kinds = match config::read_from::<FoodKinds>(FOOD_KINDS_FILE) {
+            Ok(kinds) if kinds.kinds.is_empty() => {
+                warn!("no kinds of food in {}, using defaults", FOOD_KINDS_FILE);
+                return FoodKinds::default();
+            }
+            Ok(kinds) => kinds,
+            Err(error) => {
+                warn!(
+                    "could not read {}, using defaults: {}",
+                    FOOD_KINDS_FILE, error
+                );
+                return FoodKinds::default();
+            }
+        };
+        FoodKinds {
+            kinds: kinds.kinds.into_iter().map(FoodKind::validated).collect(),
+        }
+    }
+
+    pub fn get(&self, kind: usize) -> &FoodKind {
+        &self.kinds[kind]
+    }
+
+    pub fn find(&self, id: &str) -> Option<usize> {
+        self.kinds.iter().position(|kind| kind.id == id)
+    }
+
+    /// What food goes bad into, if any
+    pub fn spoiled(&self) -> Option<usize> {
+        self.find("spoiled")
+    }
+
+    pub fn pick_for(&self, moisture: f32, rn: &mut impl Rng) -> Option<usize> {
+        let candidates = self
+            .kinds
+            .iter()
+            .enumerate()
+            .filter(|(_, kind)| kind.spawn_biome.accepts(moisture) && kind.spawn_weight > 0.0);
+        let total = candidates
+            .clone()
+            .map(|(_, kind)| kind.spawn_weight)
+            .sum::<f32>();
+        if total <= 0.0 {
+            return None;
+        }
+        let mut picked = rn.gen_range(0.0..total);
+        candidates
+            .map(|(i, kind)| {
+                picked -= kind.spawn_weight;
+                (i, picked)
+            })
+            .find(|(_, remaining)| *remaining < 0.0)
+            .map(|(i, _)| i)
+    }
+}
+
+impl Default for FoodKinds {
+    fn default() -> Self {
+        let kind = |id: &str,
+                    name: &str,
+                    color: [f32; 3],
+                    (carry_weight, nutrition, queen_food_yield): (f32, i32, f64),
+                    spawn_biome: SpawnBiome,
+                    spawn_weight: f32| FoodKind {
+            id: id.to_string(),
+            name: name.to_string(),
+            color,
+            carry_weight,
+            nutrition,
+            queen_food_yield,
+            spawn_biome,
+            spawn_weight,
+        };
+        FoodKinds {
+            kinds: vec![
+                kind(
+                    "seeds",
+                    "Seeds",
+                    [0.76, 0.6, 0.42],
+                    (1.0, 1, 0.05),
+                    SpawnBiome::Anywhere,
+                    3.0,
+                ),
+                kind(
+                    "sugar",
+                    "Sugar",
+                    [0.2, 0.4, 1.0],
+                    (0.8, 1, 0.25),
+                    SpawnBiome::Dry,
+                    1.0,
+                ),
+                kind(
+                    "protein",
+                    "Protein",
+                    [0.7, 0.2, 0.3],
+                    (1.6, 3, 0.1),
+                    SpawnBiome::Wet,
+                    1.0,
+                ),
+                kind(
+                    "spoiled",
+                    "Spoiled Food",
+                    [0.4, 0.5, 0.1],
+                    (1.0, -1, 0.0),
+                    SpawnBiome::Nowhere,
+                    0.0,
+                ),
+            ],
+        }
+    }
+}
+
 pub struct FoodHandles {
     pub mesh: Handle<bevy::render::mesh::Mesh>,
-    pub color: Handle<bevy::pbr::StandardMaterial>,
+    /// One for each kind of food
+    pub colors: Vec<Handle<bevy::pbr::StandardMaterial>>,
     pub warning: Handle<bevy::render::texture::Image>,
     pub warning_material: Handle<bevy::pbr::StandardMaterial>,
     pub warning_mesh: Handle<bevy::render::mesh::Mesh>,
@@ -58,14 +256,26 @@ impl FromWorld for FoodHandles {
             },
         ));
 
-        let mut materials = world
-            .get_resource_mut::<Assets<bevy::pbr::StandardMaterial>>()
-            .unwrap();
-        let color = materials.add(bevy::pbr::StandardMaterial {
-            base_color: bevy::render::color::Color::BLUE,
-            perceptual_roughness: 1.0,
-            metallic: 0.0,
-            ..Default::default()
+        let colors = world.resource_scope(|world, food_kinds: Mut<FoodKinds>| {
+            let mut materials = world
+                .get_resource_mut::<Assets<bevy::pbr::StandardMaterial>>()
+                .unwrap();
+            food_kinds
+                .kinds
+                .iter()
+                .map(|kind| {
+                    materials.add(bevy::pbr::StandardMaterial {
+                        base_color: bevy::render::color::Color::rgb(
+                            kind.color[0],
+                            kind.color[1],
+                            kind.color[2],
+                        ),
+                        perceptual_roughness: 1.0,
+                        metallic: 0.0,
+                        ..Default::default()
+                    })
+                })
+                .collect()
         });
 
         let warning = world
@@ -93,7 +303,7 @@ impl FromWorld for FoodHandles {
 
         Self {
             mesh,
-            color,
+            colors,
             warning,
             warning_material,
             warning_mesh,
@@ -103,17 +313,120 @@ impl FromWorld for FoodHandles {
 
 pub enum WorldEvents {
     SpawnFood(bool),
+    /// Food dropped by the player at a given spot
+    PlaceFood(Vec3),
+    /// A heap of that many pellets left by a dead anteater
+    SpawnCarcass(Vec3, usize),
+    /// A predator of the species with this index in the `PredatorRoster`
+    SpawnPredator(usize, Vec3),
     SpawnAntEater(Vec3),
+    /// A structure placed by the player, waiting for its material
+    PlaceBlueprint(StructureKind, Vec3),
+    /// An ant digging into the terrain at a given spot
+    Dig(Vec3),
 }
 
-#[derive(Component)]
-pub struct FoodPellet {
-    pub targeted: bool,
-}
+/// Number of meshes drawn for a heap, whatever its pellet count
+const HEAP_PILES: usize = 5;
+/// Scale of a pile mesh when its heap is full
+const PILE_SCALE: f32 = 3.0;
 
+/// A heap of pellets of the same kind. Pellets only become entities once an ant carries them
 #[derive(Component)]
 pub struct FoodHeap {
     start_count: usize,
+    pub kind: usize,
+    /// Pellets left, including reserved ones
+    pub count: usize,
+    /// Pellets promised to ants on their way to pick them
+    pub reserved: usize,
+}
+
+impl FoodHeap {
+    /// A heap of `count` pellets that is drawn full at `capacity`
+    pub fn new(kind: usize, count: usize, capacity: usize) -> Self {
+        FoodHeap {
+            start_count: capacity,
+            kind,
+            count,
+            reserved: 0,
+        }
+    }
+
+    pub fn available(&self) -> usize {
+        self.count - self.reserved
+    }
+
+    /// Book a pellet for an ant coming to pick it
+    pub fn reserve(&mut self) -> bool {
+        if self.available() == 0 {
+            return false;
+        }
+        self.reserved += 1;
+        true
+    }
+
+    /// Give back a pellet booked by an ant that will not pick it
+    pub fn release(&mut self) {
+        self.reserved = self.reserved.saturating_sub(1);
+    }
+
+    /// Remove a previously reserved pellet from the heap
+    pub fn take(&mut self) -> bool {
+        if self.reserved == 0 || self.count == 0 {
+            return false;
+        }
+        self.reserved -= 1;
+        self.count -= 1;
+        true
+    }
+
+    /// Remove every pellet that is not reserved, returning how many there were
+    pub fn take_available(&mut self) -> usize {
+        let available = self.available();
+        self.count -= available;
+        available
+    }
+
+    /// Remove a pellet that is not reserved, if any
+    pub fn take_one(&mut self) -> bool {
+        if self.available() == 0 {
+            return false;
+        }
+        self.count -= 1;
+        true
+    }
+
+    /// Pellets left compared to a full heap
+    pub fn fullness(&self) -> f32 {
+        self.count as f32 / self.start_count as f32
+    }
+
+    fn pile_scale(&self) -> f32 {
+        PILE_SCALE * self.fullness().sqrt()
+    }
+}
+
+/// One of the meshes drawn for a `FoodHeap`
+#[derive(Component)]
+struct HeapPile;
+
+/// Spawn the meshes of a heap at the first `HEAP_PILES` of `positions`
+pub fn spawn_piles(
+    heap: &mut ChildBuilder,
+    food_handles: &FoodHandles,
+    kind: usize,
+    positions: impl Iterator<Item = Vec3>,
+) {
+    for position in positions.take(HEAP_PILES) {
+        heap.spawn_bundle(bevy::pbr::PbrBundle {
+            mesh: food_handles.mesh.clone_weak(),
+            material: food_handles.colors[kind].clone_weak(),
+            transform: Transform::from_translation(position).with_scale(Vec3::splat(PILE_SCALE)),
+            ..Default::default()
+        })
+        .insert_bundle((HeapPile, NotShadowCaster));
+    }
 }
 
 #[derive(Component)]
@@ -122,16 +435,32 @@ pub struct FoodGoneBadTimer(Timer);
 #[derive(Component)]
 pub struct AntEaterTimer(Timer);
 
+#[allow(clippy::too_many_arguments)]
 fn spawn_food(
     mut commands: Commands,
     food_handles: Res<FoodHandles>,
     obstacle_map: Res<ObstacleMap>,
     mut events: EventReader<WorldEvents>,
     food_delay: Res<FoodDelay>,
+    food_kinds: Res<FoodKinds>,
+    noise_seeds: Res<NoiseSeeds>,
+    biomes: Res<BiomeTable>,
+    hills: Query<&Transform, With<AntHill>>,
+    heaps: Query<&Transform, With<FoodHeap>>,
+    difficulty: Res<Difficulty>,
 ) {
+    let mut placer = FoodPlacer::new(
+        &*obstacle_map,
+        &*noise_seeds,
+        &*food_kinds,
+        &*biomes,
+        hills.iter().map(|hill| hill.translation),
+        heaps.iter().map(|heap| heap.translation),
+        difficulty.0,
+    );
     for event in events.iter() {
         let mut rn = rand::thread_rng();
-        match event {
+        let placement = match event {
             WorldEvents::SpawnFood(is_nearby) => {
                 let range = if *is_nearby {
                     0.25
@@ -140,43 +469,61 @@ fn spawn_food(
                 } else {
                     BORDER * 10.0 / 11.0
                 };
-                let (x, z) = iter::repeat(())
-                    .map(|_| (rn.gen_range(-range..range), rn.gen_range(-range..range)))
-                    .find(|(x, z)| !obstacle_map.is_obstacle(*x, *z, 0.0))
-                    .unwrap();
-                let nb = rn.gen_range(80..100);
-                commands
-                    .spawn_bundle((
-                        Transform::from_xyz(x, 0.0, z),
-                        GlobalTransform::default(),
-                        FoodHeap { start_count: nb },
-                        FoodGoneBadTimer(Timer::new(
-                            Duration::from_secs_f32(food_delay.gone_bad + rn.gen_range(-5.0..5.0)),
-                            false,
-                        )),
-                    ))
-                    .with_children(|heap| {
-                        iter::repeat(())
-                            .map(|_| {
-                                Quat::from_rotation_y(rn.gen_range(0.0..(2.0 * PI)))
-                                    .mul_vec3(Vec3::X * rn.gen_range(0.0..(1.0 / DEF * 2.0)))
-                            })
-                            .filter(|pos| !obstacle_map.is_obstacle(x + pos.x, z + pos.z, 0.0))
-                            .take(nb)
-                            .for_each(|pos| {
-                                let transform = Transform::from_translation(pos);
-                                heap.spawn_bundle(bevy::pbr::PbrBundle {
-                                    mesh: food_handles.mesh.clone_weak(),
-                                    material: food_handles.color.clone_weak(),
-                                    transform,
-                                    ..Default::default()
-                                })
-                                .insert_bundle((FoodPellet { targeted: false }, NotShadowCaster));
-                            });
-                    });
+                placer
+                    .place(noise_seeds.hill(), range, &mut rn)
+                    .map(|placement| (placement, rn.gen_range(80..100)))
             }
-            WorldEvents::SpawnAntEater(_) => (),
-        }
+            WorldEvents::PlaceFood(position) => {
+                let kind = food_kinds
+                    .pick_for(noise_seeds.moisture_at(position.x, position.z), &mut rn)
+                    .unwrap_or(0);
+                let placement = Placement {
+                    position: Vec2::new(position.x, position.z),
+                    kind,
+                };
+                Some((placement, rn.gen_range(80..100)))
+            }
+            WorldEvents::SpawnCarcass(position, nb) => {
+                let placement = Placement {
+                    position: Vec2::new(position.x, position.z),
+                    kind: food_kinds.find("protein").unwrap_or(0),
+                };
+                Some((placement, *nb))
+            }
+            WorldEvents::SpawnAntEater(_)
+            | WorldEvents::SpawnPredator(_, _)
+            | WorldEvents::PlaceBlueprint(_, _)
+            | WorldEvents::Dig(_) => None,
+        };
+        let (x, z, kind, nb) = if let Some((placement, nb)) = placement {
+            (
+                placement.position.x,
+                placement.position.y,
+                placement.kind,
+                nb,
+            )
+        } else {
+            continue;
+        };
+        commands
+            .spawn_bundle((
+                Transform::from_xyz(x, 0.0, z),
+                GlobalTransform::default(),
+                FoodHeap::new(kind, nb, nb),
+                FoodGoneBadTimer(Timer::new(
+                    Duration::from_secs_f32(food_delay.gone_bad + rn.gen_range(-5.0..5.0)),
+                    false,
+                )),
+            ))
+            .with_children(|heap| {
+                let piles = iter::repeat(())
+                    .map(|_| {
+                        Quat::from_rotation_y(rn.gen_range(0.0..(2.0 * PI)))
+                            .mul_vec3(Vec3::X * rn.gen_range(0.0..(1.0 / DEF * 1.5)))
+                    })
+                    .filter(|pos| !obstacle_map.is_obstacle(x + pos.x, z + pos.z, 0.0));
+                spawn_piles(heap, &*food_handles, kind, piles);
+            });
     }
 }
 
@@ -190,18 +537,23 @@ fn pop_food(time: Res<Time>, mut timer: ResMut<FoodTimer>, mut events: EventWrit
     }
 }
 
-fn remove_empty_heaps(
-    mut commands: Commands,
-    mut heaps: Query<(Entity, &Children), With<FoodHeap>>,
-    is_warning: Query<(), With<Warning>>,
-) {
-    for (entity, children) in heaps.iter_mut() {
-        if children.len() == 0 {
+fn remove_empty_heaps(mut commands: Commands, heaps: Query<(Entity, &FoodHeap)>) {
+    for (entity, heap) in heaps.iter() {
+        if heap.count == 0 {
             commands.entity(entity).despawn_recursive();
-            continue;
         }
-        if children.len() == 1 && is_warning.get(children[0]).is_ok() {
-            commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn shrink_heaps(
+    heaps: Query<(&FoodHeap, &Children), Changed<FoodHeap>>,
+    mut piles: Query<&mut Transform, With<HeapPile>>,
+) {
+    for (heap, children) in heaps.iter() {
+        for child in children.iter() {
+            if let Ok(mut transform) = piles.get_mut(*child) {
+                transform.scale = Vec3::splat(heap.pile_scale());
+            }
         }
     }
 }
@@ -211,13 +563,23 @@ struct Warning;
 
 fn food_gone_bad(
     mut commands: Commands,
-    mut food_heaps: Query<(Entity, &mut FoodGoneBadTimer, &FoodHeap)>,
+    mut food_heaps: Query<(Entity, &mut FoodGoneBadTimer, &mut FoodHeap, &Children)>,
+    mut piles: Query<&mut Handle<bevy::pbr::StandardMaterial>, With<HeapPile>>,
     time: Res<Time>,
     food_handles: Res<FoodHandles>,
+    food_kinds: Res<FoodKinds>,
     food_delay: Res<FoodDelay>,
 ) {
-    for (entity, mut timer, food_heap) in food_heaps.iter_mut() {
+    for (entity, mut timer, mut food_heap, children) in food_heaps.iter_mut() {
         if timer.0.tick(time.delta()).just_finished() {
+            if let Some(spoiled) = food_kinds.spoiled() {
+                food_heap.kind = spoiled;
+                for child in children.iter() {
+                    if let Ok(mut material) = piles.get_mut(*child) {
+                        *material = food_handles.colors[spoiled].clone_weak();
+                    }
+                }
+            }
             commands
                 .entity(entity)
                 .with_children(|heap| {
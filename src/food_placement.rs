@@ -0,0 +1,262 @@
+use bevy::prelude::*;
+use rand::Rng;
+
+use crate::{
+    biomes::BiomeTable,
+    food::FoodKinds,
+    terrain_spawner::{NoiseSeeds, ObstacleMap},
+    BORDER, DEF,
+};
+
+/// Smallest distance between two heaps
+const HEAP_SPACING: f32 = 0.3;
+/// Ground covered by the piles of a heap
+pub const HEAP_RADIUS: f32 = 1.5 / DEF;
+/// Smallest distance between a heap and a hill, multiplied by the difficulty
+const HILL_CLEARANCE: f32 = 0.1;
+/// Candidates drawn for a heap before relaxing the spacing, then giving up
+const ATTEMPTS: usize = 30;
+/// Chances to keep a candidate on the driest ground, rising to 1.0 on the wettest
+const DRY_WEIGHT: f32 = 0.3;
+
+pub struct Placement {
+    pub position: Vec2,
+    pub kind: usize,
+}
+
+/// Poisson-disk placement of food heaps: candidates are drawn at random and rejected when
+/// too close to another heap or a hill, on an obstacle, or where no food kind grows, and
+/// kept in proportion to the moisture of the ground and the food weight of their biome
+pub struct FoodPlacer<'a> {
+    obstacle_map: &'a ObstacleMap,
+    noise_seeds: &'a NoiseSeeds,
+    food_kinds: &'a FoodKinds,
+    biomes: &'a BiomeTable,
+    hills: Vec<Vec2>,
+    heaps: Vec<Vec2>,
+    hill_clearance: f32,
+}
+
+impl<'a> FoodPlacer<'a> {
+    pub fn new(
+        obstacle_map: &'a ObstacleMap,
+        noise_seeds: &'a NoiseSeeds,
+        food_kinds: &'a FoodKinds,
+        biomes: &'a BiomeTable,
+        hills: impl Iterator<Item = Vec3>,
+        heaps: impl Iterator<Item = Vec3>,
+        difficulty: f32,
+    ) -> Self {
+        FoodPlacer {
+            obstacle_map,
+            noise_seeds,
+            food_kinds,
+            biomes,
+            hills: hills.map(|hill| Vec2::new(hill.x, hill.z)).collect(),
+            heaps: heaps.map(|heap| Vec2::new(heap.x, heap.z)).collect(),
+            hill_clearance: HILL_CLEARANCE * difficulty,
+        }
+    }
+
+    /// Find a spot for a new heap within `range` of `center`, inside the map
+    pub fn place(&mut self, center: Vec2, range: f32, rn: &mut impl Rng) -> Option<Placement> {
+        // nearby food must still fit between the hill and the range
+        let clearance = self.hill_clearance.min(range / 2.0);
+        let placement = self
+            .sample(center, range, clearance, HEAP_SPACING, rn)
+            .or_else(|| {
+                info!(
+                    "no room left for a food heap within {}, ignoring spacing",
+                    range
+                );
+                self.sample(center, range, clearance, 0.0, rn)
+            });
+        match placement {
+            Some(placement) => {
+                self.heaps.push(placement.position);
+                Some(placement)
+            }
+            None => {
+                warn!(
+                    "could not place a food heap within {} after {} attempts",
+                    range,
+                    ATTEMPTS * 2
+                );
+                None
+            }
+        }
+    }
+
+    fn sample(
+        &self,
+        center: Vec2,
+        range: f32,
+        clearance: f32,
+        spacing: f32,
+        rn: &mut impl Rng,
+    ) -> Option<Placement> {
+        let limit = BORDER - HEAP_RADIUS;
+        let from = (center - Vec2::splat(range)).max(Vec2::splat(-limit));
+        let to = (center + Vec2::splat(range)).min(Vec2::splat(limit));
+        if from.x >= to.x || from.y >= to.y {
+            return None;
+        }
+        for _ in 0..ATTEMPTS {
+            let position = Vec2::new(rn.gen_range(from.x..to.x), rn.gen_range(from.y..to.y));
+            if !self.is_free(position, clearance, spacing) {
+                continue;
+            }
+            let moisture = self.noise_seeds.moisture_at(position.x, position.y);
+            if !rn.gen_bool(self.weight(position, moisture).clamp(0.0, 1.0) as f64) {
+                continue;
+            }
+            if let Some(kind) = self.food_kinds.pick_for(moisture, rn) {
+                return Some(Placement { position, kind });
+            }
+        }
+        None
+    }
+
+    /// Chances to keep a candidate, by the moisture and the biome of the ground
+    fn weight(&self, position: Vec2, moisture: f32) -> f32 {
+        let biome_weight = self
+            .obstacle_map
+            .biome_at(position.x, position.y)
+            .map(|biome| self.biomes.get(biome).food_weight / self.biomes.max_food_weight())
+            .filter(|weight| weight.is_finite())
+            .unwrap_or(1.0);
+        (DRY_WEIGHT + (1.0 - DRY_WEIGHT) * moisture) * biome_weight
+    }
+
+    fn is_free(&self, position: Vec2, clearance: f32, spacing: f32) -> bool {
+        !self
+            .obstacle_map
+            .is_obstacle(position.x, position.y, HEAP_RADIUS)
+            && self
+                .hills
+                .iter()
+                .all(|hill| hill.distance_squared(position) >= clearance.powf(2.0))
+            && self
+                .heaps
+                .iter()
+                .all(|heap| heap.distance_squared(position) >= spacing.powf(2.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+    use crate::food::SpawnBiome;
+
+    /// Food of one kind growing anywhere, on ground where food grows the most
+    fn setup() -> (NoiseSeeds, FoodKinds, BiomeTable) {
+        let mut food_kinds = FoodKinds::default();
+        food_kinds.kinds.truncate(1);
+        food_kinds.kinds[0].spawn_biome = SpawnBiome::Anywhere;
+        let mut biomes = BiomeTable::default();
+        biomes.biomes.truncate(1);
+        biomes.biomes[0].food_weight = 1.0;
+        (NoiseSeeds::new(Some(7), None), food_kinds, biomes)
+    }
+
+    #[test]
+    fn heaps_keep_their_spacing_and_clear_of_hills() {
+        let (noise_seeds, food_kinds, biomes) = setup();
+        let map = ObstacleMap::open_ground(-2..=2);
+        let mut placer = FoodPlacer::new(
+            &map,
+            &noise_seeds,
+            &food_kinds,
+            &biomes,
+            std::iter::once(Vec3::ZERO),
+            std::iter::empty(),
+            1.0,
+        );
+        let mut rn = StdRng::seed_from_u64(1);
+        for _ in 0..8 {
+            let placement = placer.sample(Vec2::ZERO, 1.5, HILL_CLEARANCE, HEAP_SPACING, &mut rn);
+            placer.heaps.push(placement.unwrap().position);
+        }
+        let heaps = &placer.heaps;
+        for (i, heap) in heaps.iter().enumerate() {
+            assert!(heap.length() >= HILL_CLEARANCE);
+            for other in &heaps[i + 1..] {
+                assert!(heap.distance(*other) >= HEAP_SPACING);
+            }
+        }
+    }
+
+    #[test]
+    fn crowded_heaps_ignore_spacing_and_blocked_ground_gives_up() {
+        let (noise_seeds, food_kinds, biomes) = setup();
+        let map = ObstacleMap::open_ground(-2..=2);
+        // a heap already stands everywhere within spacing of the range
+        let mut placer = FoodPlacer::new(
+            &map,
+            &noise_seeds,
+            &food_kinds,
+            &biomes,
+            std::iter::empty(),
+            std::iter::once(Vec3::new(1.0, 0.0, 1.0)),
+            1.0,
+        );
+        let mut rn = StdRng::seed_from_u64(2);
+        let center = Vec2::new(1.0, 1.0);
+        assert!(placer
+            .sample(center, 0.1, 0.0, HEAP_SPACING, &mut rn)
+            .is_none());
+        let placement = placer.place(center, 0.1, &mut rn).unwrap();
+        assert!(placement.position.distance(center) <= 0.1 * 2.0_f32.sqrt());
+        assert_eq!(placer.heaps.len(), 2);
+
+        // no lot is generated, so every candidate is on an obstacle
+        let blocked = ObstacleMap::default();
+        let mut placer = FoodPlacer::new(
+            &blocked,
+            &noise_seeds,
+            &food_kinds,
+            &biomes,
+            std::iter::empty(),
+            std::iter::empty(),
+            1.0,
+        );
+        assert!(placer.place(Vec2::ZERO, 1.0, &mut rn).is_none());
+        assert!(placer.heaps.is_empty());
+    }
+
+    #[test]
+    fn heaps_favour_wet_ground_and_rich_biomes() {
+        let (noise_seeds, food_kinds, mut biomes) = setup();
+        let map = ObstacleMap::open_ground(-2..=2);
+        let placer = FoodPlacer::new(
+            &map,
+            &noise_seeds,
+            &food_kinds,
+            &biomes,
+            std::iter::empty(),
+            std::iter::empty(),
+            1.0,
+        );
+        let spot = Vec2::new(0.5, 0.5);
+        assert!((placer.weight(spot, 0.0) - DRY_WEIGHT).abs() < f32::EPSILON);
+        assert!((placer.weight(spot, 1.0) - 1.0).abs() < f32::EPSILON);
+        assert!(placer.weight(spot, 0.7) > placer.weight(spot, 0.3));
+
+        // the open ground is of the first biome, half as rich as the richest
+        biomes.biomes[0].food_weight = 0.5;
+        biomes.biomes.push(BiomeTable::default().biomes[1].clone());
+        biomes.biomes[1].food_weight = 1.0;
+        let placer = FoodPlacer::new(
+            &map,
+            &noise_seeds,
+            &food_kinds,
+            &biomes,
+            std::iter::empty(),
+            std::iter::empty(),
+            1.0,
+        );
+        assert!((placer.weight(spot, 1.0) - 0.5).abs() < f32::EPSILON);
+    }
+}
@@ -1,32 +1,63 @@
-use std::time::Duration;
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 use bevy::{
     core::{Time, Timer},
-    prelude::{Commands, Entity, EventWriter, Plugin, Query, Res, ResMut, State, SystemSet},
+    log::warn,
+    prelude::{
+        Commands, ConfigurableSystem, Entity, EventWriter, Local, Plugin, Query, Res, ResMut,
+        State, SystemSet,
+    },
     render::camera::OrthographicCameraBundle,
 };
 use bevy_egui::{egui, EguiContext};
+use serde::{Deserialize, Serialize};
 
 use crate::{
     ant_hill::AntHill,
     camera::VisibleLots,
     food::{FoodDelay, FoodTimer, WorldEvents},
-    ui::{Bonuses, GraphData},
+    scenario::{ActiveScenario, Scenario},
+    terrain_spawner::{NoiseSeeds, StartingEdits, TerrainEdits},
+    ui::GraphData,
+    upgrades::UpgradeLevels,
+    EDITS_FLAG, EXPORT_WORLD_FLAG, SCENARIO_FLAG,
 };
 
 #[derive(Clone, Eq, PartialEq, Debug, Hash)]
 pub enum GameState {
     Splash,
+    /// Choosing between a random world and the scenarios
+    Menu,
     Playing,
     Lost,
     Won,
 }
 
+/// Grows while playing, making the world harsher
+pub struct Difficulty(pub f32);
+
+impl Default for Difficulty {
+    fn default() -> Self {
+        Difficulty(1.0)
+    }
+}
+
 pub struct GameStatePlugin;
 
 impl Plugin for GameStatePlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
         app.add_state(GameState::Splash)
+            .init_resource::<SavedEdits>()
+            .add_system_set(
+                SystemSet::on_enter(GameState::Menu)
+                    .with_system(background_scene)
+                    .with_system(list_saves),
+            )
+            .add_system_set(SystemSet::on_update(GameState::Menu).with_system(menu))
+            .add_system_set(SystemSet::on_exit(GameState::Menu).with_system(despawn_all))
             .add_system_set(SystemSet::on_enter(GameState::Lost).with_system(background_scene))
             .add_system_set(SystemSet::on_update(GameState::Lost).with_system(lost_stats))
             .add_system_set(SystemSet::on_exit(GameState::Lost).with_system(despawn_all))
@@ -34,17 +65,195 @@ impl Plugin for GameStatePlugin {
             .add_system_set(SystemSet::on_update(GameState::Won).with_system(won_stats))
             .add_system_set(SystemSet::on_exit(GameState::Won).with_system(despawn_all))
             .add_system_set(SystemSet::on_enter(GameState::Playing).with_system(restart_game))
+            .add_system_set(SystemSet::on_update(GameState::Playing).with_system(
+                ramp_difficulty.config(|(_, timer, _)| {
+                    *timer = Some(Timer::new(Duration::from_secs_f32(30.0), true));
+                }),
+            ))
             .add_system_set(SystemSet::on_exit(GameState::Playing).with_system(despawn_all));
     }
 }
 
+fn ramp_difficulty(time: Res<Time>, mut timer: Local<Timer>, mut difficulty: ResMut<Difficulty>) {
+    if timer.tick(time.delta()).just_finished() {
+        difficulty.0 = (difficulty.0 + 0.1).min(3.0);
+    }
+}
+
 fn background_scene(mut commands: Commands) {
     commands.spawn_bundle(OrthographicCameraBundle::new_2d());
 }
 
+/// Files a world is saved to end with this
+const SAVE_SUFFIX: &str = "-save.conf";
+
+/// A world to continue from the menu: its seed, scenario and terrain edits
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct WorldSave {
+    seed: u64,
+    scenario: Option<PathBuf>,
+    pub edits: TerrainEdits,
+}
+
+impl WorldSave {
+    /// Saves found in the working directory, with the file they were read from
+    fn list() -> Vec<(PathBuf, WorldSave)> {
+        let entries = match std::fs::read_dir(".") {
+            Ok(entries) => entries,
+            Err(_) => return vec![],
+        };
+        let mut saves = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.to_string_lossy().ends_with(SAVE_SUFFIX))
+            .filter_map(
+                |path| match config::read_from::<WorldSave>(&path.to_string_lossy()) {
+                    Ok(save) => Some((path, save)),
+                    Err(error) => {
+                        warn!("could not read {}: {}", path.display(), error);
+                        None
+                    }
+                },
+            )
+            .collect::<Vec<_>>();
+        saves.sort_by(|a, b| a.0.cmp(&b.0));
+        saves
+    }
+}
+
+/// Saves listed in the menu, read again each time it shows up
+struct WorldSaves(Vec<(PathBuf, WorldSave)>);
+
+fn list_saves(mut commands: Commands) {
+    commands.insert_resource(WorldSaves(WorldSave::list()));
+}
+
+fn menu(
+    egui_context: Res<EguiContext>,
+    mut scenarios: Local<Option<Vec<(PathBuf, Scenario)>>>,
+    saves: Option<Res<WorldSaves>>,
+    mut active_scenario: ResMut<ActiveScenario>,
+    mut noise_seeds: ResMut<NoiseSeeds>,
+    mut starting_edits: ResMut<StartingEdits>,
+    mut state: ResMut<State<GameState>>,
+) {
+    let scenarios = scenarios.get_or_insert_with(Scenario::list);
+    let saves = saves.as_ref().map_or(&[][..], |saves| &saves.0[..]);
+    let mut chosen = None;
+    let mut continued = None;
+    egui::Window::new("Ants Of Unusual Shape")
+        .collapsible(false)
+        .resizable(false)
+        .show(egui_context.ctx(), |ui| {
+            if ui.button("Random world").clicked() {
+                chosen = Some(None);
+            }
+            for (index, (_, scenario)) in scenarios.iter().enumerate() {
+                ui.separator();
+                if ui.button(&scenario.name).clicked() {
+                    chosen = Some(Some(index));
+                }
+                ui.label(&scenario.description);
+            }
+            for (index, (path, save)) in saves.iter().enumerate() {
+                ui.separator();
+                if ui.button(format!("Continue world {}", save.seed)).clicked() {
+                    continued = Some(index);
+                }
+                ui.label(format!("Saved in {}", path.display()));
+            }
+        });
+    if let Some(index) = continued {
+        let save = &saves[index];
+        // a save of a scenario plays it again, when it is still there
+        let scenario = match &save.1.scenario {
+            Some(file) => match scenarios.iter().position(|(path, _)| path == file) {
+                Some(scenario) => Some(scenario),
+                None => {
+                    warn!(
+                        "the scenario {} of {} is gone",
+                        file.display(),
+                        save.0.display()
+                    );
+                    return;
+                }
+            },
+            None => None,
+        };
+        *noise_seeds = active_scenario.select(
+            scenario.map(|index| {
+                let (path, scenario) = &scenarios[index];
+                (path.as_path(), scenario)
+            }),
+            Some(save.1.seed),
+        );
+        starting_edits.0 = save.1.edits.clone();
+        let _ = state.set(GameState::Playing);
+    } else if let Some(chosen) = chosen {
+        *noise_seeds = active_scenario.select(
+            chosen.map(|index| {
+                let (path, scenario) = &scenarios[index];
+                (path.as_path(), scenario)
+            }),
+            None,
+        );
+        starting_edits.0 = TerrainEdits::default();
+        let _ = state.set(GameState::Playing);
+    }
+}
+
+/// The outcome of saving the world of the game that just ended
+#[derive(Default)]
+struct SavedEdits(Option<String>);
+
+/// Shows the seed of the world, and saves it to be continued or exported with
+/// `EXPORT_WORLD_FLAG`
+fn world_info(
+    ui: &mut egui::Ui,
+    noise_seeds: &NoiseSeeds,
+    scenario: &ActiveScenario,
+    edits: &TerrainEdits,
+    saved: &mut SavedEdits,
+) {
+    ui.label(format!("World seed: {}", noise_seeds.seed()));
+    if let Some(path) = scenario.path() {
+        ui.label(format!("Scenario: {}", path.display()));
+    }
+    if ui.button("Save world").clicked() {
+        let file = format!("world-{}{}", noise_seeds.seed(), SAVE_SUFFIX);
+        let save = WorldSave {
+            seed: noise_seeds.seed(),
+            scenario: scenario.path().map(Path::to_path_buf),
+            edits: edits.clone(),
+        };
+        saved.0 = Some(match config::write_to(&file, &save) {
+            Ok(()) => {
+                let scenario = scenario.path().map_or(String::new(), |path| {
+                    format!(" {} {}", SCENARIO_FLAG, path.display())
+                });
+                format!(
+                    "Continue it from the menu, or export with: aous {} {}{} {} {}",
+                    EXPORT_WORLD_FLAG,
+                    noise_seeds.seed(),
+                    scenario,
+                    EDITS_FLAG,
+                    file
+                )
+            }
+            Err(error) => format!("Could not save {}: {}", file, error),
+        });
+    }
+    if let Some(saved) = &saved.0 {
+        ui.label(saved);
+    }
+}
+
 fn lost_stats(
     egui_context: Res<EguiContext>,
     data: Res<GraphData>,
+    noise_seeds: Res<NoiseSeeds>,
+    scenario: Res<ActiveScenario>,
+    edits: Res<TerrainEdits>,
+    mut saved: ResMut<SavedEdits>,
     mut state: ResMut<State<GameState>>,
 ) {
     egui::Window::new("All your ants died!")
@@ -60,16 +269,24 @@ fn lost_stats(
                 "You had a maximum of {} ants, with {} spawned.",
                 data.max_ants, data.total_ants
             ));
+            world_info(ui, &*noise_seeds, &*scenario, &*edits, &mut *saved);
             ui.label("");
             if ui.button("Restart!").clicked() {
                 let _ = state.set(GameState::Playing);
             }
+            if ui.button("Menu").clicked() {
+                let _ = state.set(GameState::Menu);
+            }
         });
 }
 
 fn won_stats(
     egui_context: Res<EguiContext>,
     data: Res<GraphData>,
+    noise_seeds: Res<NoiseSeeds>,
+    scenario: Res<ActiveScenario>,
+    edits: Res<TerrainEdits>,
+    mut saved: ResMut<SavedEdits>,
     mut state: ResMut<State<GameState>>,
 ) {
     egui::Window::new("Your colony is now self sufficient!")
@@ -85,23 +302,28 @@ fn won_stats(
                 "You had a maximum of {} ants, with {} spawned.",
                 data.max_ants, data.total_ants
             ));
+            world_info(ui, &*noise_seeds, &*scenario, &*edits, &mut *saved);
             ui.label("");
             if ui.button("Restart!").clicked() {
                 let _ = state.set(GameState::Playing);
             }
+            if ui.button("Menu").clicked() {
+                let _ = state.set(GameState::Menu);
+            }
         });
 }
 
 fn restart_game(mut commands: Commands, time: Res<Time>, mut events: EventWriter<WorldEvents>) {
-    commands.insert_resource(AntHill::default());
     commands.insert_resource(FoodDelay::default());
+    commands.insert_resource(Difficulty::default());
     commands.insert_resource(GraphData::from_anthill(AntHill::default(), &*time));
     commands.insert_resource(VisibleLots::default());
     let duration = Duration::from_secs_f32(19.0);
     let mut new_timer = Timer::new(duration, true);
     new_timer.set_elapsed(duration * 99 / 100);
     commands.insert_resource(FoodTimer(new_timer));
-    commands.insert_resource(Bonuses::default());
+    commands.insert_resource(UpgradeLevels::default());
+    commands.insert_resource(SavedEdits::default());
     events.send(WorldEvents::SpawnFood(true));
 }
 
@@ -16,19 +16,47 @@ use bevy_egui::EguiPlugin;
 mod ant_eaters;
 mod ant_hill;
 mod ants;
+mod biomes;
+mod build_tools;
 mod camera;
+mod combat;
 mod food;
+mod food_placement;
 mod game_state;
+mod picking;
+mod plants;
+mod predators;
+mod rivals;
+mod scenario;
 mod splash;
+mod structures;
 mod terrain_spawner;
+mod threats;
 mod ui;
+mod upgrades;
 
 // struct RaycastCameraToGround;
 
 const BORDER: f32 = 2.0;
 const DEF: f32 = 20.0;
 
+/// Writes images of the world built from a seed instead of playing:
+/// `--export-world <seed> [<from x> <from z> <to x> <to z>] [--scenario <file>] [--edits <file>]`,
+/// in lots, with the terrain of a scenario and the edits of a world saved at the end of a game
+const EXPORT_WORLD_FLAG: &str = "--export-world";
+const SCENARIO_FLAG: &str = "--scenario";
+const EDITS_FLAG: &str = "--edits";
+
 fn main() {
+    let args = std::env::args().collect::<Vec<_>>();
+    if let Some(flag) = args.iter().position(|arg| arg == EXPORT_WORLD_FLAG) {
+        if let Err(error) = export_world(&args[flag + 1..]) {
+            eprintln!("could not export the world: {}", error);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     App::new()
         .insert_resource(WindowDescriptor {
             title: "Ants Of Unusual Shape".to_string(),
@@ -54,11 +82,20 @@ fn main() {
         .add_plugin(game_state::GameStatePlugin)
         .add_plugin(splash::SplashPlugin)
         .add_plugin(camera::CameraPlugin)
+        .add_plugin(picking::PickingPlugin)
         .add_plugin(terrain_spawner::TerrainSpawnerPlugin)
         .add_plugin(ants::AntsPlugin)
         .add_plugin(ant_hill::AntHillPlugin)
         .add_plugin(food::FoodPlugin)
+        .add_plugin(plants::PlantsPlugin)
+        .add_plugin(build_tools::BuildToolsPlugin)
+        .add_plugin(structures::StructuresPlugin)
         .add_plugin(ant_eaters::AntEatersPlugin)
+        .add_plugin(predators::PredatorsPlugin)
+        .add_plugin(combat::CombatPlugin)
+        .add_plugin(threats::ThreatsPlugin)
+        .add_plugin(rivals::RivalsPlugin)
+        .add_plugin(scenario::ScenarioPlugin)
         // .init_resource::<CursorPosition>()
         // .add_system_to_stage(
         //     CoreStage::PreUpdate,
@@ -70,10 +107,68 @@ fn main() {
         //         .label(RaycastSystem::UpdateDebugCursor)
         //         .after(RaycastSystem::UpdateRaycast),
         // )
+        .add_plugin(upgrades::UpgradesPlugin)
         .add_plugin(ui::UiPlugin)
         .run();
 }
 
+fn export_world(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut values = vec![];
+    let (mut scenario, mut edits) = (None, None);
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            SCENARIO_FLAG => scenario = Some(args.next().ok_or("missing the scenario file")?),
+            EDITS_FLAG => edits = Some(args.next().ok_or("missing the edits file")?),
+            _ => values.push(arg),
+        }
+    }
+    let seed = values
+        .get(0)
+        .ok_or("missing the seed of the world")?
+        .parse::<u64>()?;
+    let lots = BORDER as i32 + 1;
+    let region = match values[1..] {
+        [from_x, from_z, to_x, to_z, ..] => (
+            IVec2::new(from_x.parse()?, from_z.parse()?),
+            IVec2::new(to_x.parse()?, to_z.parse()?),
+        ),
+        _ => (IVec2::splat(-lots), IVec2::splat(lots)),
+    };
+    let layout = match scenario {
+        Some(file) => Some(
+            read_existing::<scenario::Scenario>(file)?.terrain_layout(std::path::Path::new(file)),
+        ),
+        None => None,
+    };
+    let edits = match edits {
+        Some(file) => read_existing::<game_state::WorldSave>(file)?.edits,
+        None => terrain_spawner::TerrainEdits::default(),
+    };
+    let prefix = format!("world-{}", seed);
+    terrain_spawner::export_images(
+        &terrain_spawner::NoiseSeeds::new(Some(seed), layout),
+        &biomes::BiomeTable::load(),
+        &edits,
+        region.0,
+        region.1,
+        &prefix,
+    )?;
+    println!("wrote {}-*.png", prefix);
+    Ok(())
+}
+
+/// Like `config::read_from`, but a missing file is an error rather than the defaults
+fn read_existing<T>(file: &str) -> Result<T, Box<dyn std::error::Error>>
+where
+    T: Default + serde::Serialize + serde::de::DeserializeOwned,
+{
+    if !std::path::Path::new(file).exists() {
+        return Err(format!("{} not found", file).into());
+    }
+    config::read_from(file)
+}
+
 // fn update_raycast_with_cursor(
 //     mut cursor: EventReader<CursorMoved>,
 //     mut query: Query<&mut RayCastSource<RaycastCameraToGround>>,
@@ -0,0 +1,95 @@
+use bevy::{
+    prelude::*,
+    render::camera::{Camera, PerspectiveProjection},
+};
+
+use crate::{
+    game_state::GameState,
+    terrain_spawner::{NoiseSeeds, TerrainEdits},
+};
+
+/// Rays start being marched at this height, above the highest terrain
+const CEILING: f32 = 0.6;
+const STEP: f32 = 0.02;
+const MAX_DISTANCE: f32 = 15.0;
+/// Halvings of the last step to refine the hit
+const REFINE: usize = 8;
+
+pub struct PickingPlugin;
+
+impl Plugin for PickingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TerrainCursor>()
+            .add_system_set(SystemSet::on_update(GameState::Playing).with_system(pick_terrain));
+    }
+}
+
+/// Point of the terrain under the mouse cursor, if any
+#[derive(Default)]
+pub struct TerrainCursor(pub Option<Vec3>);
+
+fn pick_terrain(
+    windows: Res<Windows>,
+    camera: Query<(&Camera, &GlobalTransform), With<PerspectiveProjection>>,
+    noise_seeds: Res<NoiseSeeds>,
+    edits: Res<TerrainEdits>,
+    mut cursor: ResMut<TerrainCursor>,
+) {
+    cursor.0 = None;
+    let window = if let Some(window) = windows.get_primary() {
+        window
+    } else {
+        return;
+    };
+    let (camera, transform) = if let Ok(camera) = camera.get_single() {
+        camera
+    } else {
+        return;
+    };
+    if let Some(position) = window.cursor_position() {
+        let ndc = position / Vec2::new(window.width(), window.height()) * 2.0 - Vec2::ONE;
+        let (origin, direction) = camera_ray(camera, transform, ndc);
+        cursor.0 = pick(origin, direction, noise_seeds.heightfield(&*edits));
+    }
+}
+
+fn camera_ray(camera: &Camera, transform: &GlobalTransform, ndc: Vec2) -> (Vec3, Vec3) {
+    let ndc_to_world = transform.compute_matrix() * camera.projection_matrix.inverse();
+    // the projection has a reversed depth: 1.0 is the near plane
+    let near = ndc_to_world.project_point3(ndc.extend(1.0));
+    let further = ndc_to_world.project_point3(ndc.extend(0.1));
+    (near, (further - near).normalize())
+}
+
+/// First point where the ray goes below the heightfield
+fn pick(origin: Vec3, direction: Vec3, height_at: impl Fn(f32, f32) -> f32) -> Option<Vec3> {
+    if direction.y >= 0.0 && origin.y > CEILING {
+        return None;
+    }
+    let start = if origin.y > CEILING {
+        (origin.y - CEILING) / -direction.y
+    } else {
+        0.0
+    };
+    let is_below = |t: f32| {
+        let point = origin + direction * t;
+        point.y <= height_at(point.x, point.z)
+    };
+    let mut t = start;
+    while t < start + MAX_DISTANCE {
+        if is_below(t + STEP) {
+            let (mut above, mut below) = (t, t + STEP);
+            for _ in 0..REFINE {
+                let middle = (above + below) / 2.0;
+                if is_below(middle) {
+                    below = middle;
+                } else {
+                    above = middle;
+                }
+            }
+            return Some(origin + direction * below);
+        }
+        t += STEP;
+    }
+    None
+}
@@ -0,0 +1,148 @@
+use std::f32::consts::PI;
+
+use bevy::{pbr::NotShadowCaster, prelude::*};
+use rand::Rng;
+
+use crate::{
+    food::{spawn_piles, FoodHandles, FoodHeap, FoodKinds},
+    food_placement::HEAP_RADIUS,
+    game_state::GameState,
+    terrain_spawner::{NoiseSeeds, ObstacleMap},
+    BORDER, DEF,
+};
+
+const PLANTS: usize = 12;
+/// Candidates drawn for each plant, wet ones are more likely to be kept
+const ATTEMPTS: usize = 50;
+/// Plants don't grow this close to the player hill
+const CLEARING: f32 = 0.4;
+/// Seconds to grow a pellet when the plant is half full
+const GROWTH_PERIOD: f32 = 1.5;
+
+pub struct PlantsPlugin;
+
+impl Plugin for PlantsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PlantHandles>()
+            .add_system_set(SystemSet::on_enter(GameState::Playing).with_system(spawn_plants))
+            .add_system_set(SystemSet::on_update(GameState::Playing).with_system(grow_plants));
+    }
+}
+
+struct PlantHandles {
+    stem_mesh: Handle<bevy::render::mesh::Mesh>,
+    stem_color: Handle<bevy::pbr::StandardMaterial>,
+}
+
+impl FromWorld for PlantHandles {
+    fn from_world(world: &mut World) -> Self {
+        let stem_mesh = world
+            .get_resource_mut::<Assets<bevy::render::mesh::Mesh>>()
+            .unwrap()
+            .add(bevy::render::mesh::Mesh::from(
+                bevy::render::mesh::shape::Capsule {
+                    radius: 0.01,
+                    depth: 0.08,
+                    latitudes: 4,
+                    longitudes: 6,
+                    ..Default::default()
+                },
+            ));
+        let stem_color = world
+            .get_resource_mut::<Assets<bevy::pbr::StandardMaterial>>()
+            .unwrap()
+            .add(bevy::pbr::StandardMaterial {
+                base_color: bevy::render::color::Color::rgb(0.25, 0.55, 0.2),
+                perceptual_roughness: 1.0,
+                metallic: 0.0,
+                ..Default::default()
+            });
+        Self {
+            stem_mesh,
+            stem_color,
+        }
+    }
+}
+
+/// A `FoodHeap` that regrows its pellets. It grows fastest when half full, so a plant
+/// harvested down to a few pellets barely recovers, and one stripped bare dies
+#[derive(Component)]
+pub struct Plant {
+    timer: Timer,
+}
+
+fn spawn_plants(
+    mut commands: Commands,
+    plant_handles: Res<PlantHandles>,
+    food_handles: Res<FoodHandles>,
+    food_kinds: Res<FoodKinds>,
+    noise_seeds: Res<NoiseSeeds>,
+    obstacle_map: Res<ObstacleMap>,
+) {
+    let mut rn = rand::thread_rng();
+    let range = BORDER * 10.0 / 11.0;
+    let hill = noise_seeds.hill();
+    for _ in 0..PLANTS {
+        // plants favour wet ground
+        let spot = (0..ATTEMPTS).find_map(|_| {
+            let spot = Vec2::new(rn.gen_range(-range..range), rn.gen_range(-range..range));
+            let moisture = noise_seeds.moisture_at(spot.x, spot.y).clamp(0.0, 1.0);
+            (spot.distance(hill) > CLEARING
+                && !obstacle_map.is_obstacle(spot.x, spot.y, HEAP_RADIUS)
+                && rn.gen_bool(moisture as f64))
+            .then(|| spot)
+        });
+        let (spot, kind) = match spot.and_then(|spot| {
+            food_kinds
+                .pick_for(noise_seeds.moisture_at(spot.x, spot.y), &mut rn)
+                .map(|kind| (spot, kind))
+        }) {
+            Some(plant) => plant,
+            None => {
+                debug!("no spot found for a plant");
+                continue;
+            }
+        };
+        let capacity = rn.gen_range(20..40);
+        commands
+            .spawn_bundle((
+                Transform::from_xyz(spot.x, 0.0, spot.y),
+                GlobalTransform::default(),
+                FoodHeap::new(kind, capacity / 2, capacity),
+                Plant {
+                    timer: Timer::from_seconds(GROWTH_PERIOD, true),
+                },
+            ))
+            .with_children(|plant| {
+                plant
+                    .spawn_bundle(bevy::pbr::PbrBundle {
+                        mesh: plant_handles.stem_mesh.clone_weak(),
+                        material: plant_handles.stem_color.clone_weak(),
+                        transform: Transform::from_xyz(0.0, 0.05, 0.0),
+                        ..Default::default()
+                    })
+                    .insert(NotShadowCaster);
+                let piles = (0..).map(|i| {
+                    Quat::from_rotation_y(i as f32 * 2.0 * PI / 5.0).mul_vec3(Vec3::new(
+                        1.0 / DEF,
+                        0.02,
+                        0.0,
+                    ))
+                });
+                spawn_piles(plant, &*food_handles, kind, piles);
+            });
+    }
+}
+
+fn grow_plants(time: Res<Time>, mut plants: Query<(&mut Plant, &mut FoodHeap)>) {
+    for (mut plant, mut heap) in plants.iter_mut() {
+        let fullness = heap.fullness();
+        if fullness >= 1.0 {
+            continue;
+        }
+        let rate = (4.0 * fullness * (1.0 - fullness)).max(0.1);
+        if plant.timer.tick(time.delta().mul_f32(rate)).just_finished() {
+            heap.count += 1;
+        }
+    }
+}
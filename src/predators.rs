@@ -0,0 +1,640 @@
+use std::f32::consts::{FRAC_PI_2, PI};
+
+use bevy::{
+    pbr::NotShadowCaster,
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ant_hill::Colony,
+    ants::Creature,
+    combat::Health,
+    food::{FoodHeap, WorldEvents},
+    game_state::GameState,
+    terrain_spawner::{NoiseSeeds, ObstacleMap},
+    ui::GraphData,
+    BORDER,
+};
+
+const PREDATORS_FILE: &str = "predators.conf";
+/// Ants with fewer neighbours than this within `LONE_RANGE` are alone in the open
+const LONE_NEIGHBOURS: usize = 3;
+const LONE_RANGE: f32 = 0.1;
+/// Seconds a swooping predator climbs back before hunting again
+const SWOOP_COOLDOWN: f32 = 3.0;
+/// Radius of the circles flown while looking for prey
+const CIRCLE_RADIUS: f32 = 0.4;
+/// Candidates drawn to find a spot matching a spawn rule
+const SPAWN_ATTEMPTS: usize = 40;
+
+pub struct PredatorsPlugin;
+
+impl Plugin for PredatorsPlugin {
+    fn build(&self, app: &mut App) {
+        let roster = match config::read_from::<PredatorRoster>(PREDATORS_FILE) {
+            Ok(roster) => roster,
+            Err(error) => {
+                warn!(
+                    "could not read {}, using defaults: {}",
+                    PREDATORS_FILE, error
+                );
+                PredatorRoster::default()
+            }
+        };
+        app.insert_resource(roster)
+            .init_resource::<PredatorHandles>()
+            .init_resource::<SpawnSchedule>()
+            .add_system_set(SystemSet::on_enter(GameState::Playing).with_system(reset_spawns))
+            .add_system_set(
+                SystemSet::on_update(GameState::Playing)
+                    .with_system(schedule_predators)
+                    .with_system(spawn_predators)
+                    .with_system(hunt)
+                    .with_system(predators_leave),
+            );
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum Diet {
+    Ants,
+    Food,
+    Both,
+}
+
+impl Diet {
+    fn eats_ants(&self) -> bool {
+        matches!(self, Diet::Ants | Diet::Both)
+    }
+
+    fn eats_food(&self) -> bool {
+        matches!(self, Diet::Food | Diet::Both)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum Behaviour {
+    /// Circle high above, dive on ants alone in the open
+    Swoop,
+    /// Wait next to an obstacle, lunge at ants passing by
+    Ambush,
+    /// Drift across the map, hitting everything under it
+    Sweep,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum SpawnTerrain {
+    Anywhere,
+    /// Away from any obstacle
+    Open,
+    NearObstacle,
+    Wet,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct SpawnRule {
+    /// Seconds into the game before the first one
+    pub after: f32,
+    /// Seconds between two attempts
+    pub every: f32,
+    pub chance: f64,
+    /// Player ants needed for it to show up
+    pub min_ants: u32,
+    pub terrain: SpawnTerrain,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum Shape {
+    Sphere,
+    Capsule,
+    Plane,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct Look {
+    pub shape: Shape,
+    pub size: f32,
+    /// `[red, green, blue, alpha]`
+    pub color: [f32; 4],
+    /// Height it moves at when not attacking
+    pub height: f32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PredatorSpecies {
+    pub id: String,
+    pub name: String,
+    pub speed: f32,
+    pub diet: Diet,
+    /// Distance at which prey is noticed
+    pub sense: f32,
+    /// Distance at which prey is hit
+    pub reach: f32,
+    /// Prey taken per second in reach; for sweeping predators, chance for each prey to be hit
+    pub kills_per_second: f32,
+    /// Predators without health can't be hurt
+    pub health: Option<f32>,
+    pub behaviour: Behaviour,
+    /// Seconds before it leaves
+    pub lifetime: f32,
+    pub spawn: SpawnRule,
+    pub look: Look,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PredatorRoster {
+    pub species: Vec<PredatorSpecies>,
+}
+
+impl Default for PredatorRoster {
+    fn default() -> Self {
+        PredatorRoster {
+            species: vec![
+                PredatorSpecies {
+                    id: "bird".to_string(),
+                    name: "Bird".to_string(),
+                    speed: 0.5,
+                    diet: Diet::Ants,
+                    sense: 0.5,
+                    reach: 0.04,
+                    kills_per_second: 1.0,
+                    health: Some(6.0),
+                    behaviour: Behaviour::Swoop,
+                    lifetime: 40.0,
+                    spawn: SpawnRule {
+                        after: 60.0,
+                        every: 45.0,
+                        chance: 0.6,
+                        min_ants: 30,
+                        terrain: SpawnTerrain::Open,
+                    },
+                    look: Look {
+                        shape: Shape::Capsule,
+                        size: 0.025,
+                        color: [0.35, 0.25, 0.15, 1.0],
+                        height: 0.4,
+                    },
+                },
+                PredatorSpecies {
+                    id: "spider".to_string(),
+                    name: "Spider".to_string(),
+                    speed: 0.3,
+                    diet: Diet::Ants,
+                    sense: 0.15,
+                    reach: 0.05,
+                    kills_per_second: 2.0,
+                    health: Some(15.0),
+                    behaviour: Behaviour::Ambush,
+                    lifetime: 90.0,
+                    spawn: SpawnRule {
+                        after: 30.0,
+                        every: 40.0,
+                        chance: 0.5,
+                        min_ants: 15,
+                        terrain: SpawnTerrain::NearObstacle,
+                    },
+                    look: Look {
+                        shape: Shape::Sphere,
+                        size: 0.03,
+                        color: [0.05, 0.05, 0.05, 1.0],
+                        height: 0.02,
+                    },
+                },
+                PredatorSpecies {
+                    id: "rain".to_string(),
+                    name: "Rain".to_string(),
+                    speed: 0.15,
+                    diet: Diet::Both,
+                    sense: 0.0,
+                    reach: 0.35,
+                    kills_per_second: 0.3,
+                    health: None,
+                    behaviour: Behaviour::Sweep,
+                    lifetime: 25.0,
+                    spawn: SpawnRule {
+                        after: 90.0,
+                        every: 60.0,
+                        chance: 0.5,
+                        min_ants: 0,
+                        terrain: SpawnTerrain::Wet,
+                    },
+                    look: Look {
+                        shape: Shape::Plane,
+                        size: 0.7,
+                        color: [0.3, 0.45, 0.9, 0.35],
+                        height: 0.3,
+                    },
+                },
+            ],
+        }
+    }
+}
+
+struct PredatorHandles {
+    /// One mesh and material for each species
+    meshes: Vec<Handle<bevy::render::mesh::Mesh>>,
+    materials: Vec<Handle<bevy::pbr::StandardMaterial>>,
+}
+
+impl FromWorld for PredatorHandles {
+    fn from_world(world: &mut World) -> Self {
+        world.resource_scope(|world, roster: Mut<PredatorRoster>| {
+            let mut meshes = world
+                .get_resource_mut::<Assets<bevy::render::mesh::Mesh>>()
+                .unwrap();
+            let meshes = roster
+                .species
+                .iter()
+                .map(|species| {
+                    let size = species.look.size;
+                    meshes.add(match species.look.shape {
+                        Shape::Sphere => {
+                            bevy::render::mesh::Mesh::from(bevy::render::mesh::shape::Icosphere {
+                                radius: size,
+                                subdivisions: 2,
+                            })
+                        }
+                        Shape::Capsule => {
+                            bevy::render::mesh::Mesh::from(bevy::render::mesh::shape::Capsule {
+                                radius: size / 2.0,
+                                depth: size * 2.0,
+                                ..Default::default()
+                            })
+                        }
+                        Shape::Plane => {
+                            bevy::render::mesh::Mesh::from(bevy::render::mesh::shape::Plane {
+                                size,
+                            })
+                        }
+                    })
+                })
+                .collect();
+            let mut materials = world
+                .get_resource_mut::<Assets<bevy::pbr::StandardMaterial>>()
+                .unwrap();
+            let materials = roster
+                .species
+                .iter()
+                .map(|species| {
+                    let [red, green, blue, alpha] = species.look.color;
+                    materials.add(bevy::pbr::StandardMaterial {
+                        base_color: bevy::render::color::Color::rgba(red, green, blue, alpha),
+                        perceptual_roughness: 1.0,
+                        metallic: 0.0,
+                        alpha_mode: if alpha < 1.0 {
+                            bevy::pbr::AlphaMode::Blend
+                        } else {
+                            bevy::pbr::AlphaMode::Opaque
+                        },
+                        ..Default::default()
+                    })
+                })
+                .collect();
+            PredatorHandles { meshes, materials }
+        })
+    }
+}
+
+#[derive(Component)]
+pub struct Predator {
+    pub species: usize,
+    /// Where it circles or waits
+    anchor: Vec3,
+    heading: Vec3,
+    prey: Option<Entity>,
+    cooldown: f32,
+    /// Prey taken when it reaches 1.0
+    bite: f32,
+    age: f32,
+}
+
+/// Next time each species tries to show up, in seconds into the game
+#[derive(Default)]
+struct SpawnSchedule(Vec<f32>);
+
+fn reset_spawns(mut schedule: ResMut<SpawnSchedule>, roster: Res<PredatorRoster>) {
+    schedule.0 = roster
+        .species
+        .iter()
+        .map(|species| species.spawn.after)
+        .collect();
+}
+
+#[allow(clippy::too_many_arguments)]
+fn schedule_predators(
+    mut schedule: ResMut<SpawnSchedule>,
+    roster: Res<PredatorRoster>,
+    data: Res<GraphData>,
+    time: Res<Time>,
+    creatures: Query<&Creature>,
+    obstacle_map: Res<ObstacleMap>,
+    noise_seeds: Res<NoiseSeeds>,
+    mut events: EventWriter<WorldEvents>,
+) {
+    let elapsed = (time.time_since_startup() - data.start_time).as_secs_f32();
+    let mut rn = rand::thread_rng();
+    for (i, species) in roster.species.iter().enumerate() {
+        if schedule.0.get(i).map_or(true, |next| elapsed < *next) {
+            continue;
+        }
+        schedule.0[i] = elapsed + species.spawn.every * rn.gen_range(0.7..1.3);
+        let ants = creatures
+            .iter()
+            .filter(|ant| ant.colony == Colony::PLAYER)
+            .count() as u32;
+        if ants < species.spawn.min_ants || !rn.gen_bool(species.spawn.chance) {
+            continue;
+        }
+        let range = BORDER * 10.0 / 11.0;
+        let spot = (0..SPAWN_ATTEMPTS)
+            .map(|_| {
+                Vec3::new(
+                    rn.gen_range(-range..range),
+                    0.0,
+                    rn.gen_range(-range..range),
+                )
+            })
+            .find(|spot| {
+                if obstacle_map.is_obstacle(spot.x, spot.z, 0.0) {
+                    return false;
+                }
+                let near_obstacle = (0..8)
+                    .map(|k| {
+                        *spot + Quat::from_rotation_y(k as f32 * PI / 4.0).mul_vec3(Vec3::X * 0.1)
+                    })
+                    .any(|around| obstacle_map.is_obstacle(around.x, around.z, 0.0));
+                match species.spawn.terrain {
+                    SpawnTerrain::Anywhere => true,
+                    SpawnTerrain::Open => !near_obstacle,
+                    SpawnTerrain::NearObstacle => near_obstacle,
+                    SpawnTerrain::Wet => noise_seeds.moisture_at(spot.x, spot.z) > 0.6,
+                }
+            });
+        match spot {
+            Some(spot) => events.send(WorldEvents::SpawnPredator(i, spot)),
+            None => debug!("no spot found for a {}", species.name),
+        }
+    }
+}
+
+fn spawn_predators(
+    mut commands: Commands,
+    mut events: EventReader<WorldEvents>,
+    roster: Res<PredatorRoster>,
+    handles: Res<PredatorHandles>,
+) {
+    for event in events.iter() {
+        if let WorldEvents::SpawnPredator(i, position) = event {
+            let species = &roster.species[*i];
+            debug!("a {} shows up at {}", species.name, position);
+            let heading = if species.behaviour == Behaviour::Sweep {
+                // drift over the middle of the map
+                (-*position).normalize_or_zero()
+            } else {
+                Vec3::X
+            };
+            let rotation = if species.look.shape == Shape::Capsule {
+                Quat::from_rotation_x(FRAC_PI_2)
+            } else {
+                Quat::IDENTITY
+            };
+            let mut entity = commands.spawn_bundle(bevy::pbr::PbrBundle {
+                mesh: handles.meshes[*i].clone_weak(),
+                material: handles.materials[*i].clone_weak(),
+                transform: Transform {
+                    translation: *position + Vec3::Y * species.look.height,
+                    rotation,
+                    ..Default::default()
+                },
+                ..Default::default()
+            });
+            entity.insert_bundle((
+                Predator {
+                    species: *i,
+                    anchor: *position,
+                    heading,
+                    prey: None,
+                    cooldown: 0.0,
+                    bite: 0.0,
+                    age: 0.0,
+                },
+                NotShadowCaster,
+            ));
+            if let Some(health) = species.health {
+                entity.insert(Health::new(health));
+            }
+        }
+    }
+}
+
+fn hunt(
+    mut predators: Query<(&mut Transform, &mut Predator)>,
+    mut ants: Query<(Entity, &Transform, &mut Health), (With<Creature>, Without<Predator>)>,
+    mut food_heaps: Query<(&Transform, &mut FoodHeap), (Without<Creature>, Without<Predator>)>,
+    roster: Res<PredatorRoster>,
+    obstacle_map: Res<ObstacleMap>,
+    time: Res<Time>,
+) {
+    let delta = time.delta_seconds();
+    let mut rn = rand::thread_rng();
+    // found once a frame, when a swooping predator first looks for prey
+    let mut lone = None;
+    for (mut transform, mut predator) in predators.iter_mut() {
+        let species = &roster.species[predator.species];
+        predator.age += delta;
+        predator.cooldown -= delta;
+        let flat = |position: Vec3| Vec3::new(position.x, 0.0, position.z);
+        let here = transform.translation;
+
+        match species.behaviour {
+            Behaviour::Swoop => {
+                // keep diving on the same ant while it lives
+                let prey = predator
+                    .prey
+                    .and_then(|prey| ants.get(prey).ok())
+                    .filter(|(_, _, health)| !health.is_dead())
+                    .map(|(entity, ant, _)| (entity, ant.translation));
+                let prey = prey.or_else(|| {
+                    if predator.cooldown > 0.0 || !species.diet.eats_ants() {
+                        return None;
+                    }
+                    let lone = lone.get_or_insert_with(|| lone_ants(&ants));
+                    ants.iter()
+                        .find(|(entity, ant, _)| {
+                            flat(ant.translation).distance_squared(flat(here))
+                                < species.sense.powf(2.0)
+                                && lone.contains(entity)
+                        })
+                        .map(|(entity, ant, _)| (entity, ant.translation))
+                });
+                predator.prey = prey.map(|(entity, _)| entity);
+                transform.translation = match prey {
+                    Some((entity, target)) => {
+                        let dive = here
+                            + (target - here).normalize_or_zero() * species.speed * 2.0 * delta;
+                        if dive.distance_squared(target) < species.reach.powf(2.0) {
+                            if let Ok((_, _, mut health)) = ants.get_mut(entity) {
+                                health.current = 0.0;
+                            }
+                            predator.prey = None;
+                            predator.cooldown = SWOOP_COOLDOWN;
+                        }
+                        dive
+                    }
+                    None => {
+                        // circle around the anchor while climbing back
+                        let angle = predator.age * species.speed / CIRCLE_RADIUS;
+                        let circling = predator.anchor
+                            + Quat::from_rotation_y(angle).mul_vec3(Vec3::X * CIRCLE_RADIUS)
+                            + Vec3::Y * species.look.height;
+                        here + (circling - here).clamp_length_max(species.speed * delta)
+                    }
+                };
+            }
+            Behaviour::Ambush => {
+                let nearest = ants
+                    .iter()
+                    .filter(|(_, _, health)| !health.is_dead())
+                    .map(|(entity, ant, _)| (entity, ant.translation))
+                    .filter(|(_, ant)| {
+                        flat(*ant).distance_squared(predator.anchor) < species.sense.powf(2.0)
+                    })
+                    .min_by(|(_, a), (_, b)| {
+                        a.distance_squared(here)
+                            .partial_cmp(&b.distance_squared(here))
+                            .unwrap()
+                    });
+                let goal = match nearest {
+                    Some((_, ant)) if species.diet.eats_ants() => flat(ant),
+                    _ => predator.anchor,
+                } + Vec3::Y * species.look.height;
+                // flying and drifting predators don't feel the ground, walking ones do
+                let speed =
+                    species.speed * obstacle_map.speed_factor(here.x, here.z, flat(goal - here));
+                let step = (goal - here).clamp_length_max(speed * delta);
+                // walk around obstacles and water, sliding along them when blocked
+                let radius = species.look.size / 2.0;
+                let can_walk = |to: Vec3| {
+                    if obstacle_map.is_obstacle(here.x, here.z, radius) {
+                        // out of something it was caught in
+                        !obstacle_map.is_obstacle(to.x, to.z, radius)
+                    } else {
+                        obstacle_map.is_path_clear(
+                            Vec2::new(here.x, here.z),
+                            Vec2::new(to.x, to.z),
+                            radius,
+                        )
+                    }
+                };
+                if let Some(to) = [
+                    here + step,
+                    here + step * Vec3::new(1.0, 1.0, 0.0),
+                    here + step * Vec3::new(0.0, 1.0, 1.0),
+                ]
+                .into_iter()
+                .find(|to| can_walk(*to))
+                {
+                    transform.translation = to;
+                }
+                if let Some((entity, ant)) = nearest {
+                    if flat(ant).distance_squared(flat(here)) < species.reach.powf(2.0)
+                        && species.diet.eats_ants()
+                    {
+                        predator.bite += species.kills_per_second * delta;
+                        if predator.bite >= 1.0 {
+                            predator.bite -= 1.0;
+                            if let Ok((_, _, mut health)) = ants.get_mut(entity) {
+                                health.current = 0.0;
+                            }
+                        }
+                    }
+                }
+            }
+            Behaviour::Sweep => {
+                transform.translation = here + predator.heading * species.speed * delta;
+                let chance = (species.kills_per_second * delta).min(1.0) as f64;
+                if species.diet.eats_ants() {
+                    for (_, ant, mut health) in ants.iter_mut() {
+                        if flat(ant.translation).distance_squared(flat(here))
+                            < species.reach.powf(2.0)
+                            && rn.gen_bool(chance)
+                        {
+                            health.current = 0.0;
+                        }
+                    }
+                }
+            }
+        }
+
+        // predators eating food nibble at heaps in reach
+        if species.diet.eats_food() {
+            let here = flat(transform.translation);
+            for (heap_transform, mut heap) in food_heaps.iter_mut() {
+                if flat(heap_transform.translation).distance_squared(here) < species.reach.powf(2.0)
+                    && heap.available() > 0
+                    && rn.gen_bool((species.kills_per_second * delta).min(1.0) as f64)
+                {
+                    heap.take_one();
+                }
+            }
+        }
+    }
+}
+
+/// Ants with at most `LONE_NEIGHBOURS` others, themselves included, within `LONE_RANGE`
+fn lone_ants(
+    ants: &Query<(Entity, &Transform, &mut Health), (With<Creature>, Without<Predator>)>,
+) -> HashSet<Entity> {
+    let cell = |position: Vec3| {
+        IVec2::new(
+            (position.x / LONE_RANGE).floor() as i32,
+            (position.z / LONE_RANGE).floor() as i32,
+        )
+    };
+    let mut grid: HashMap<IVec2, Vec<Vec3>> = HashMap::default();
+    for (_, transform, _) in ants.iter() {
+        grid.entry(cell(transform.translation))
+            .or_insert_with(Vec::new)
+            .push(transform.translation);
+    }
+    ants.iter()
+        .filter(|(_, transform, _)| {
+            let around = cell(transform.translation);
+            (-1..=1)
+                .flat_map(|i| (-1..=1).map(move |j| around + IVec2::new(i, j)))
+                .filter_map(|neighbour| grid.get(&neighbour))
+                .flatten()
+                .filter(|other| {
+                    other.distance_squared(transform.translation) < LONE_RANGE.powf(2.0)
+                })
+                .count()
+                <= LONE_NEIGHBOURS
+        })
+        .map(|(entity, _, _)| entity)
+        .collect()
+}
+
+fn predators_leave(
+    mut commands: Commands,
+    predators: Query<(Entity, &Transform, &Predator, Option<&Health>)>,
+    roster: Res<PredatorRoster>,
+) {
+    for (entity, transform, predator, health) in predators.iter() {
+        let species = &roster.species[predator.species];
+        // sweeping predators leave once they crossed the map
+        let gone = species.behaviour == Behaviour::Sweep
+            && transform
+                .translation
+                .x
+                .abs()
+                .max(transform.translation.z.abs())
+                > BORDER;
+        if predator.age > species.lifetime || gone || health.map_or(false, Health::is_dead) {
+            debug!("the {} is gone", species.name);
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
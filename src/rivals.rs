@@ -0,0 +1,151 @@
+use std::f32::consts::PI;
+
+use bevy::{prelude::*, utils::HashMap};
+use rand::{prelude::SliceRandom, Rng};
+
+use crate::{
+    ant_hill::{AntHill, AntHillHandles, Colony, HillEvent},
+    ants::Creature,
+    combat::{Attack, Health},
+    game_state::GameState,
+    terrain_spawner::NoiseSeeds,
+    upgrades::{UpgradeLevels, UpgradeTree},
+    BORDER, DEF,
+};
+
+const RIVAL_COLONIES: u8 = 2;
+const FIGHT_DISTANCE: f32 = 0.03;
+
+pub struct RivalsPlugin;
+
+impl Plugin for RivalsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(SystemSet::on_enter(GameState::Playing).with_system(spawn_rivals))
+            .add_system_set(
+                SystemSet::on_update(GameState::Playing)
+                    .with_system(rival_strategy)
+                    .with_system(colony_fights),
+            );
+    }
+}
+
+/// Spends the queen food of a rival hill on the same upgrades as the player
+#[derive(Component)]
+pub struct RivalAi {
+    levels: UpgradeLevels,
+    timer: Timer,
+}
+
+fn spawn_rivals(
+    mut commands: Commands,
+    ant_hill_handles: Res<AntHillHandles>,
+    noise_seeds: Res<NoiseSeeds>,
+) {
+    // rival hills share a ring around the center of the map with the player's hill
+    let hill = noise_seeds.hill();
+    let first_angle = if hill.length_squared() > f32::EPSILON {
+        // the angle rotating X onto the hill, as the ring positions below do
+        (-hill.y).atan2(hill.x)
+    } else {
+        rand::thread_rng().gen_range(0.0..(2.0 * PI))
+    };
+    for i in 1..=RIVAL_COLONIES {
+        let angle = first_angle + i as f32 * 2.0 * PI / (RIVAL_COLONIES + 1) as f32;
+        let position = Quat::from_rotation_y(angle).mul_vec3(Vec3::X * BORDER * 0.75);
+        commands
+            .spawn_bundle(bevy::pbr::PbrBundle {
+                mesh: ant_hill_handles.mesh.clone_weak(),
+                material: ant_hill_handles.rival_color.clone_weak(),
+                transform: Transform::from_xyz(position.x, -0.02, position.z),
+                ..Default::default()
+            })
+            .insert_bundle((
+                AntHill::default(),
+                Colony(i),
+                RivalAi {
+                    levels: UpgradeLevels::default(),
+                    timer: Timer::from_seconds(5.0, true),
+                },
+            ));
+    }
+}
+
+fn rival_strategy(
+    mut rivals: Query<(Entity, &mut AntHill, &mut RivalAi)>,
+    tree: Res<UpgradeTree>,
+    time: Res<Time>,
+    mut events: EventWriter<HillEvent>,
+) {
+    for (entity, mut hill, mut ai) in rivals.iter_mut() {
+        if !ai.timer.tick(time.delta()).just_finished() {
+            continue;
+        }
+        let affordable = tree
+            .upgrades
+            .iter()
+            .filter(|upgrade| {
+                ai.levels.is_unlocked(upgrade)
+                    && !ai.levels.is_maxed(upgrade)
+                    && ai.levels.cost(upgrade) <= hill.queen_food
+            })
+            .collect::<Vec<_>>();
+        if let Some(upgrade) = affordable.choose(&mut rand::thread_rng()) {
+            debug!("colony of {:?} buys {}", entity, upgrade.id);
+            for event in ai
+                .levels
+                .buy(upgrade, &mut hill.queen_food)
+                .unwrap_or_default()
+            {
+                events.send(HillEvent {
+                    hill: entity,
+                    event,
+                });
+            }
+        }
+    }
+}
+
+/// Ants bite an enemy of another colony in reach, their death handled like any other
+fn colony_fights(
+    ants: Query<(Entity, &Transform, &Creature, &Attack)>,
+    mut healths: Query<&mut Health, With<Creature>>,
+    time: Res<Time>,
+) {
+    let cell = |position: Vec3| {
+        IVec2::new(
+            (position.x * DEF).floor() as i32,
+            (position.z * DEF).floor() as i32,
+        )
+    };
+    let mut grid: HashMap<IVec2, Vec<(Entity, Vec3, Colony)>> = HashMap::default();
+    for (entity, transform, ant, _) in ants.iter() {
+        grid.entry(cell(transform.translation))
+            .or_insert_with(Vec::new)
+            .push((entity, transform.translation, ant.colony));
+    }
+
+    let mut damages: HashMap<Entity, f32> = HashMap::default();
+    for (_, transform, ant, attack) in ants.iter() {
+        let around = cell(transform.translation);
+        let enemy = (-1..=1)
+            .flat_map(|i| (-1..=1).map(move |j| around + IVec2::new(i, j)))
+            .filter_map(|neighbour| grid.get(&neighbour))
+            .flatten()
+            .find(|(other, position, colony)| {
+                *colony != ant.colony
+                    && healths
+                        .get(*other)
+                        .map_or(false, |health| !health.is_dead())
+                    && position.distance_squared(transform.translation) < FIGHT_DISTANCE.powf(2.0)
+            });
+        if let Some((other, _, _)) = enemy {
+            *damages.entry(*other).or_insert(0.0) += attack.0 * time.delta_seconds();
+        }
+    }
+
+    for (entity, damage) in damages {
+        if let Ok(mut health) = healths.get_mut(entity) {
+            health.current -= damage;
+        }
+    }
+}
@@ -0,0 +1,313 @@
+use std::path::{Path, PathBuf};
+
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContext};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ant_hill::{AntHill, Colony, MainHill},
+    ants::Creature,
+    food::WorldEvents,
+    game_state::GameState,
+    predators::PredatorRoster,
+    terrain_spawner::{Heightmap, NoiseSeeds, TerrainLayout},
+    threats::{threat_event, Threat},
+    ui::GraphData,
+};
+
+/// Scenarios are read from the `.conf` files in this directory next to the game
+const SCENARIOS_DIR: &str = "scenarios";
+const SCENARIO_EXTENSION: &str = "conf";
+
+pub struct ScenarioPlugin;
+
+impl Plugin for ScenarioPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ActiveScenario>()
+            .init_resource::<ScenarioProgress>()
+            .add_system_set(SystemSet::on_enter(GameState::Playing).with_system(start_scenario))
+            .add_system_set(
+                SystemSet::on_update(GameState::Playing)
+                    .with_system(play_script)
+                    .with_system(track_objectives),
+            );
+    }
+}
+
+/// A disk of terrain with a set elevation, from -1.0 (basin) to 1.0 (obstacle)
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct TerrainOverride {
+    pub center: (f32, f32),
+    pub radius: f32,
+    pub elevation: f32,
+}
+
+/// A food heap dropped `at` seconds after the start
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ScriptedFood {
+    pub at: f32,
+    pub position: (f32, f32),
+}
+
+/// A threat coming `at` seconds after the start
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ScriptedThreat {
+    pub at: f32,
+    pub threat: Threat,
+    pub position: (f32, f32),
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub enum Objective {
+    /// Food in the queen's reserve
+    QueenFood(u32),
+    /// Ants alive at once
+    Ants(u32),
+    /// Seconds since the start
+    Survive(f32),
+}
+
+impl Objective {
+    fn is_met(&self, queen_food: u32, ants: u32, elapsed: f32) -> bool {
+        match *self {
+            Objective::QueenFood(target) => queen_food >= target,
+            Objective::Ants(target) => ants >= target,
+            Objective::Survive(seconds) => elapsed >= seconds,
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Objective::QueenFood(target) => format!("Gather {} food for the queen", target),
+            Objective::Ants(target) => format!("Grow to {} ants", target),
+            Objective::Survive(seconds) => format!("Survive for {:.0} seconds", seconds),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Scenario {
+    pub name: String,
+    pub description: String,
+    /// Seed of the noise, random when missing
+    pub seed: Option<u64>,
+    /// Grayscale PNG replacing the elevation noise over the map, relative to the scenario file
+    pub heightmap: Option<String>,
+    /// Applied over the heightmap or the noise, the last one first where they overlap
+    pub overrides: Vec<TerrainOverride>,
+    /// Where the main hill stands
+    pub hill: (f32, f32),
+    pub food: Vec<ScriptedFood>,
+    pub threats: Vec<ScriptedThreat>,
+    /// All must be met to win, the usual goals apply when there are none
+    pub objectives: Vec<Objective>,
+}
+
+impl Default for Scenario {
+    fn default() -> Self {
+        Scenario {
+            name: "Scenario".to_string(),
+            description: String::new(),
+            seed: None,
+            heightmap: None,
+            overrides: vec![],
+            hill: (0.0, 0.0),
+            food: vec![],
+            threats: vec![],
+            objectives: vec![],
+        }
+    }
+}
+
+impl Scenario {
+    /// Scenarios found next to the game, with the file they were read from
+    pub fn list() -> Vec<(PathBuf, Scenario)> {
+        let entries = match std::fs::read_dir(SCENARIOS_DIR) {
+            Ok(entries) => entries,
+            Err(_) => return vec![],
+        };
+        let mut scenarios = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.extension()
+                    .map_or(false, |extension| extension == SCENARIO_EXTENSION)
+            })
+            .filter_map(
+                |path| match config::read_from::<Scenario>(&path.to_string_lossy()) {
+                    Ok(scenario) => Some((path, scenario)),
+                    Err(error) => {
+                        warn!("could not read {}: {}", path.display(), error);
+                        None
+                    }
+                },
+            )
+            .collect::<Vec<_>>();
+        scenarios.sort_by(|a, b| a.0.cmp(&b.0));
+        scenarios
+    }
+
+    /// The terrain of the scenario, its heightmap read relative to the scenario file at `path`
+    pub fn terrain_layout(&self, path: &Path) -> TerrainLayout {
+        let heightmap = self.heightmap.as_ref().and_then(|file| {
+            let file = path.parent().unwrap_or_else(|| Path::new("")).join(file);
+            Heightmap::load(&file)
+                .map_err(|error| warn!("could not read {}, using noise: {}", file.display(), error))
+                .ok()
+        });
+        TerrainLayout {
+            heightmap,
+            overrides: self.overrides.clone(),
+            hill: Vec2::new(self.hill.0, self.hill.1),
+        }
+    }
+}
+
+/// The scenario being played with the file it was read from, none on a random world
+#[derive(Default)]
+pub struct ActiveScenario(Option<(PathBuf, Scenario)>);
+
+impl ActiveScenario {
+    /// Plays a scenario read from a file, or a random world, returning the seeds of its terrain,
+    /// drawn from `seed` when given rather than the one of the scenario
+    pub fn select(
+        &mut self,
+        scenario: Option<(&Path, &Scenario)>,
+        seed: Option<u64>,
+    ) -> NoiseSeeds {
+        let (path, mut scenario) = match scenario {
+            Some((path, scenario)) => (path, scenario.clone()),
+            None => {
+                self.0 = None;
+                return NoiseSeeds::new(seed, None);
+            }
+        };
+        scenario
+            .food
+            .sort_by(|a, b| a.at.partial_cmp(&b.at).unwrap());
+        scenario
+            .threats
+            .sort_by(|a, b| a.at.partial_cmp(&b.at).unwrap());
+        let noise_seeds =
+            NoiseSeeds::new(seed.or(scenario.seed), Some(scenario.terrain_layout(path)));
+        self.0 = Some((path.to_path_buf(), scenario));
+        noise_seeds
+    }
+
+    pub fn has_objectives(&self) -> bool {
+        self.0
+            .as_ref()
+            .map_or(false, |(_, scenario)| !scenario.objectives.is_empty())
+    }
+
+    /// The file the scenario was read from
+    pub fn path(&self) -> Option<&Path> {
+        self.0.as_ref().map(|(path, _)| path.as_path())
+    }
+}
+
+#[derive(Default)]
+struct ScenarioProgress {
+    started: f64,
+    /// Scripted food heaps and threats already sent
+    food: usize,
+    threats: usize,
+}
+
+fn start_scenario(mut progress: ResMut<ScenarioProgress>, time: Res<Time>) {
+    *progress = ScenarioProgress {
+        started: time.seconds_since_startup(),
+        ..Default::default()
+    };
+}
+
+fn play_script(
+    scenario: Res<ActiveScenario>,
+    mut progress: ResMut<ScenarioProgress>,
+    roster: Res<PredatorRoster>,
+    time: Res<Time>,
+    mut events: EventWriter<WorldEvents>,
+) {
+    let scenario = if let Some((_, scenario)) = &scenario.0 {
+        scenario
+    } else {
+        return;
+    };
+    let elapsed = (time.seconds_since_startup() - progress.started) as f32;
+    while let Some(food) = scenario
+        .food
+        .get(progress.food)
+        .filter(|food| food.at <= elapsed)
+    {
+        events.send(WorldEvents::PlaceFood(Vec3::new(
+            food.position.0,
+            0.0,
+            food.position.1,
+        )));
+        progress.food += 1;
+    }
+    while let Some(spawn) = scenario
+        .threats
+        .get(progress.threats)
+        .filter(|spawn| spawn.at <= elapsed)
+    {
+        let position = Vec3::new(spawn.position.0, 0.0, spawn.position.1);
+        match threat_event(&spawn.threat, position, &*roster) {
+            Some(event) => events.send(event),
+            None => warn!(
+                "unknown predator {:?} in scenario {}",
+                spawn.threat, scenario.name
+            ),
+        }
+        progress.threats += 1;
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn track_objectives(
+    egui_context: Res<EguiContext>,
+    scenario: Res<ActiveScenario>,
+    progress: Res<ScenarioProgress>,
+    main_hill: Query<&AntHill, With<MainHill>>,
+    creatures: Query<&Creature>,
+    time: Res<Time>,
+    mut data: ResMut<GraphData>,
+    mut state: ResMut<State<GameState>>,
+) {
+    let scenario = match &scenario.0 {
+        Some((_, scenario)) if !scenario.objectives.is_empty() => scenario,
+        _ => return,
+    };
+    let queen_food = if let Ok(anthill) = main_hill.get_single() {
+        anthill.queen_food
+    } else {
+        return;
+    };
+    let ants = creatures
+        .iter()
+        .filter(|creature| creature.colony == Colony::PLAYER)
+        .count() as u32;
+    let elapsed = (time.seconds_since_startup() - progress.started) as f32;
+
+    let met = scenario
+        .objectives
+        .iter()
+        .map(|objective| objective.is_met(queen_food, ants, elapsed))
+        .collect::<Vec<_>>();
+    egui::Window::new(&scenario.name)
+        .anchor(egui::Align2::RIGHT_TOP, [-10.0, 10.0])
+        .collapsible(true)
+        .resizable(false)
+        .show(egui_context.ctx(), |ui| {
+            for (objective, met) in scenario.objectives.iter().zip(&met) {
+                ui.label(format!(
+                    "{} {}",
+                    if *met { "[x]" } else { "[ ]" },
+                    objective.describe()
+                ));
+            }
+        });
+    if ants > 0 && met.iter().all(|met| *met) {
+        data.end_time = time.time_since_startup();
+        let _ = state.set(GameState::Won);
+    }
+}
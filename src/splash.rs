@@ -68,7 +68,7 @@ fn done(time: Res<Time>, mut screen: ResMut<Screen>, mut state: ResMut<State<Gam
     if let Some(ref mut timer) = screen.done {
         timer.tick(time.delta());
         if timer.just_finished() {
-            state.set(GameState::Playing).unwrap();
+            state.set(GameState::Menu).unwrap();
         }
     }
 }
@@ -0,0 +1,344 @@
+use bevy::prelude::*;
+
+use crate::{
+    ant_hill::{Colony, MainHill},
+    ants::ANT_RADIUS,
+    combat::Health,
+    food::WorldEvents,
+    game_state::GameState,
+    terrain_spawner::{ObstacleMap, WATER_LEVEL},
+};
+
+const WALL_LENGTH: f32 = 0.3;
+const WALL_THICKNESS: f32 = 0.05;
+const BRIDGE_LENGTH: f32 = 0.5;
+const RAFT_LENGTH: f32 = 0.2;
+const CROSSING_WIDTH: f32 = 0.08;
+/// Health lost per second by a built raft as its leaves rot
+const RAFT_ROT: f32 = 1.0;
+/// Ants near a watch post sense food this much further
+pub const WATCH_BOOST: f32 = 2.0;
+pub const WATCH_RANGE: f32 = 0.8;
+/// Ants closer than this to a depot can drop their food there
+pub const DEPOT_RANGE: f32 = 0.1;
+/// Ants closer than this to a blueprint can add their material to it
+pub const BUILD_RANGE: f32 = 0.08;
+
+pub struct StructuresPlugin;
+
+impl Plugin for StructuresPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<StructureHandles>().add_system_set(
+            SystemSet::on_update(GameState::Playing)
+                .with_system(place_blueprints)
+                .with_system(complete_structures)
+                .with_system(rafts_rot)
+                .with_system(structures_crumble),
+        );
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum StructureKind {
+    Wall,
+    Depot,
+    WatchPost,
+    Bridge,
+    /// A short crossing made of leaves, rotting away once built
+    Raft,
+}
+
+impl StructureKind {
+    fn index(&self) -> usize {
+        match self {
+            StructureKind::Wall => 0,
+            StructureKind::Depot => 1,
+            StructureKind::WatchPost => 2,
+            StructureKind::Bridge => 3,
+            StructureKind::Raft => 4,
+        }
+    }
+
+    /// Structures built over water, that let creatures cross it along their length
+    pub fn crosses_water(&self) -> bool {
+        self.crossing_length().is_some()
+    }
+
+    fn crossing_length(&self) -> Option<f32> {
+        match self {
+            StructureKind::Bridge => Some(BRIDGE_LENGTH),
+            StructureKind::Raft => Some(RAFT_LENGTH),
+            _ => None,
+        }
+    }
+
+    /// Loads of material workers have to bring
+    pub fn material(&self) -> u32 {
+        match self {
+            StructureKind::Wall => 20,
+            StructureKind::Depot => 30,
+            StructureKind::WatchPost => 15,
+            StructureKind::Bridge => 40,
+            StructureKind::Raft => 8,
+        }
+    }
+
+    fn health(&self) -> f32 {
+        match self {
+            StructureKind::Wall => 30.0,
+            StructureKind::Depot => 20.0,
+            StructureKind::WatchPost => 15.0,
+            StructureKind::Bridge => 40.0,
+            StructureKind::Raft => 30.0,
+        }
+    }
+}
+
+/// A structure of the colony, a blueprint until all its material is delivered
+#[derive(Component)]
+pub struct Structure {
+    pub kind: StructureKind,
+    /// Where workers bring the material
+    pub site: Vec3,
+    delivered: u32,
+    /// Loads on their way
+    reserved: u32,
+    pub built: bool,
+}
+
+impl Structure {
+    fn new(kind: StructureKind, site: Vec3) -> Self {
+        Structure {
+            kind,
+            site,
+            delivered: 0,
+            reserved: 0,
+            built: false,
+        }
+    }
+
+    /// Claims a load of material for a worker, if more is still needed
+    pub fn reserve(&mut self) -> bool {
+        if self.built || self.delivered + self.reserved >= self.kind.material() {
+            return false;
+        }
+        self.reserved += 1;
+        true
+    }
+
+    pub fn release(&mut self) {
+        self.reserved = self.reserved.saturating_sub(1);
+    }
+
+    /// Adds a reserved load of material
+    pub fn deliver(&mut self) {
+        self.release();
+        self.delivered += 1;
+    }
+
+    pub fn is_built(&self, kind: StructureKind) -> bool {
+        self.built && self.kind == kind
+    }
+}
+
+struct StructureHandles {
+    meshes: Vec<Handle<bevy::render::mesh::Mesh>>,
+    colors: Vec<Handle<bevy::pbr::StandardMaterial>>,
+    blueprint_color: Handle<bevy::pbr::StandardMaterial>,
+}
+
+impl FromWorld for StructureHandles {
+    fn from_world(world: &mut World) -> Self {
+        let mut meshes = world
+            .get_resource_mut::<Assets<bevy::render::mesh::Mesh>>()
+            .unwrap();
+        let structure_meshes = vec![
+            meshes.add(bevy::render::mesh::Mesh::from(
+                bevy::render::mesh::shape::Box::new(WALL_LENGTH, 0.08, WALL_THICKNESS),
+            )),
+            meshes.add(bevy::render::mesh::Mesh::from(
+                bevy::render::mesh::shape::Cube { size: 0.1 },
+            )),
+            meshes.add(bevy::render::mesh::Mesh::from(
+                bevy::render::mesh::shape::Capsule {
+                    radius: 0.02,
+                    depth: 0.25,
+                    latitudes: 2,
+                    longitudes: 6,
+                    ..Default::default()
+                },
+            )),
+            meshes.add(bevy::render::mesh::Mesh::from(
+                bevy::render::mesh::shape::Box::new(CROSSING_WIDTH, 0.02, BRIDGE_LENGTH),
+            )),
+            meshes.add(bevy::render::mesh::Mesh::from(
+                bevy::render::mesh::shape::Box::new(CROSSING_WIDTH, 0.01, RAFT_LENGTH),
+            )),
+        ];
+
+        let mut materials = world
+            .get_resource_mut::<Assets<bevy::pbr::StandardMaterial>>()
+            .unwrap();
+        let colors = [
+            bevy::render::color::Color::rgb(0.5, 0.4, 0.3),
+            bevy::render::color::Color::rgb(0.6, 0.35, 0.15),
+            bevy::render::color::Color::rgb(0.4, 0.3, 0.2),
+            bevy::render::color::Color::rgb(0.45, 0.3, 0.15),
+            bevy::render::color::Color::rgb(0.3, 0.55, 0.2),
+        ]
+        .iter()
+        .map(|color| {
+            materials.add(bevy::pbr::StandardMaterial {
+                base_color: *color,
+                perceptual_roughness: 1.0,
+                metallic: 0.0,
+                ..Default::default()
+            })
+        })
+        .collect();
+        let blueprint_color = materials.add(bevy::pbr::StandardMaterial {
+            base_color: bevy::render::color::Color::rgba(0.6, 0.8, 1.0, 0.4),
+            alpha_mode: bevy::pbr::AlphaMode::Blend,
+            unlit: true,
+            ..Default::default()
+        });
+
+        Self {
+            meshes: structure_meshes,
+            colors,
+            blueprint_color,
+        }
+    }
+}
+
+fn place_blueprints(
+    mut commands: Commands,
+    mut events: EventReader<WorldEvents>,
+    main_hill: Query<&Transform, With<MainHill>>,
+    handles: Res<StructureHandles>,
+    obstacle_map: Res<ObstacleMap>,
+) {
+    for event in events.iter() {
+        if let WorldEvents::PlaceBlueprint(kind, position) = event {
+            let translation = match kind {
+                StructureKind::WatchPost => *position + Vec3::Y * 0.15,
+                StructureKind::Bridge | StructureKind::Raft => {
+                    Vec3::new(position.x, WATER_LEVEL + 0.01, position.z)
+                }
+                _ => *position + Vec3::Y * 0.03,
+            };
+            // walls face away from the colony, crossings lead away from it
+            let outward = main_hill
+                .get_single()
+                .map(|hill| *position - hill.translation)
+                .unwrap_or(*position);
+            let outward = Vec3::new(outward.x, 0.0, outward.z).normalize_or_zero();
+            // workers bring material to the end of crossings on the colony side, on the
+            // closest ground they can walk to
+            let site = *position - outward * kind.crossing_length().unwrap_or(0.0) / 2.0;
+            let site = kind
+                .crossing_length()
+                .and_then(|length| obstacle_map.nearest_free(site.x, site.z, ANT_RADIUS, length))
+                .map_or(site, |free| Vec3::new(free.x, site.y, free.y));
+            commands
+                .spawn_bundle(bevy::pbr::PbrBundle {
+                    mesh: handles.meshes[kind.index()].clone_weak(),
+                    material: handles.blueprint_color.clone_weak(),
+                    transform: Transform {
+                        translation,
+                        rotation: Quat::from_rotation_y(outward.x.atan2(outward.z)),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                })
+                .insert_bundle((
+                    Structure::new(*kind, site),
+                    Health::new(kind.health()),
+                    Colony::PLAYER,
+                    bevy::pbr::NotShadowCaster,
+                ));
+        }
+    }
+}
+
+/// Points every `spacing` along a structure, on its local `axis`
+fn segment(
+    transform: &Transform,
+    axis: Vec3,
+    length: f32,
+    spacing: f32,
+) -> impl Iterator<Item = Vec3> {
+    let along = transform.rotation.mul_vec3(axis);
+    let center = transform.translation;
+    let steps = (length / spacing) as i32;
+    (-steps / 2..=steps / 2).map(move |i| center + along * i as f32 * spacing)
+}
+
+/// Cells a wall blocks along its length
+fn wall_segment(transform: &Transform) -> impl Iterator<Item = Vec3> {
+    segment(transform, Vec3::X, WALL_LENGTH, WALL_THICKNESS)
+}
+
+/// Water cells a crossing covers along its length
+fn crossing_segment(transform: &Transform, length: f32) -> impl Iterator<Item = Vec3> {
+    segment(transform, Vec3::Z, length, CROSSING_WIDTH / 2.0)
+}
+
+fn complete_structures(
+    mut commands: Commands,
+    mut structures: Query<(Entity, &Transform, &mut Structure), Changed<Structure>>,
+    mut obstacle_map: ResMut<ObstacleMap>,
+    handles: Res<StructureHandles>,
+) {
+    for (entity, transform, mut structure) in structures.iter_mut() {
+        if structure.built || structure.delivered < structure.kind.material() {
+            continue;
+        }
+        structure.built = true;
+        if structure.kind == StructureKind::Wall {
+            for point in wall_segment(transform) {
+                obstacle_map.place_obstacle(point.x, point.z, WALL_THICKNESS / 2.0);
+            }
+        }
+        if let Some(length) = structure.kind.crossing_length() {
+            for point in crossing_segment(transform, length) {
+                obstacle_map.place_crossing(point.x, point.z, CROSSING_WIDTH / 2.0);
+            }
+        }
+        commands
+            .entity(entity)
+            .remove::<bevy::pbr::NotShadowCaster>()
+            .insert(handles.colors[structure.kind.index()].clone_weak());
+    }
+}
+
+fn structures_crumble(
+    mut commands: Commands,
+    structures: Query<(Entity, &Transform, &Structure, &Health)>,
+    mut obstacle_map: ResMut<ObstacleMap>,
+) {
+    for (entity, transform, structure, health) in structures.iter() {
+        if !health.is_dead() {
+            continue;
+        }
+        if structure.is_built(StructureKind::Wall) {
+            for point in wall_segment(transform) {
+                obstacle_map.remove_obstacle(point.x, point.z, WALL_THICKNESS / 2.0);
+            }
+        }
+        if let (true, Some(length)) = (structure.built, structure.kind.crossing_length()) {
+            for point in crossing_segment(transform, length) {
+                obstacle_map.remove_crossing(point.x, point.z, CROSSING_WIDTH / 2.0);
+            }
+        }
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn rafts_rot(mut rafts: Query<(&Structure, &mut Health)>, time: Res<Time>) {
+    for (structure, mut health) in rafts.iter_mut() {
+        if structure.is_built(StructureKind::Raft) {
+            health.current -= RAFT_ROT * time.delta_seconds();
+        }
+    }
+}
@@ -1,14 +1,22 @@
-use std::collections::hash_map::Entry;
+use std::{collections::hash_map::Entry, path::Path, sync::Arc, time::Duration};
 
 use bevy::{
-    ecs::component::SparseStorage, math::const_vec3, pbr::NotShadowCaster, prelude::*,
-    utils::HashMap,
+    ecs::component::SparseStorage,
+    pbr::NotShadowCaster,
+    prelude::*,
+    tasks::{AsyncComputeTaskPool, Task},
+    utils::{HashMap, HashSet},
 };
 // use bevy_mod_raycast::{BoundVol, RayCastMesh};
 use bracket_noise::prelude::{FastNoise, FractalType, NoiseType};
-use rand::Rng;
+use futures_lite::future;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
 
-use crate::{game_state::GameState, BORDER, DEF};
+use crate::{
+    ant_hill::Colony, ants::Creature, biomes::BiomeTable, camera::VisibleLots, food::WorldEvents,
+    game_state::GameState, scenario::TerrainOverride, BORDER, DEF,
+};
 
 #[derive(Debug)]
 pub struct EmptyLot {
@@ -33,86 +41,716 @@ impl EmptyLot {
 
 pub struct TerrainSpawnerPlugin;
 
+/// How fast the ground of a cell can be crossed
+#[derive(Clone, Copy, Debug)]
+pub struct GroundCell {
+    /// Rise of the terrain per distance along x and z
+    pub gradient: Vec2,
+    /// From 0.0 (arid) to 1.0 (wet)
+    pub moisture: f32,
+    /// Index in the `BiomeTable`
+    pub biome: usize,
+    /// Of the biome, kept here to be read without the table
+    pub movement_cost: f32,
+}
+
+mod ground {
+    /// Speed lost per rise of the terrain per distance when climbing, gained when going down
+    pub const CLIMB: f32 = 12.0;
+    pub const SLOWEST: f32 = 0.5;
+    pub const FASTEST: f32 = 1.2;
+    /// Angles creatures turn from their heading to find easier ground
+    pub const DETOURS: [f32; 4] = [
+        -std::f32::consts::FRAC_PI_6,
+        std::f32::consts::FRAC_PI_6,
+        -std::f32::consts::FRAC_PI_3,
+        std::f32::consts::FRAC_PI_3,
+    ];
+}
+
+impl GroundCell {
+    /// Multiplier of the speed of a creature crossing the cell in `heading`
+    pub fn speed_factor(&self, heading: Vec3) -> f32 {
+        let rise = self
+            .gradient
+            .dot(Vec2::new(heading.x, heading.z).normalize_or_zero());
+        let slope = (1.0 - ground::CLIMB * rise).clamp(ground::SLOWEST, ground::FASTEST);
+        slope / self.movement_cost.max(0.1)
+    }
+}
+
+/// Cells along each side of a chunk, the cells of one lot
+const CHUNK_SIDE: i32 = DEF as i32;
+const CHUNK_CELLS: usize = (CHUNK_SIDE * CHUNK_SIDE) as usize;
+const CHUNK_WORDS: usize = (CHUNK_CELLS + 63) / 64;
+/// Chunks of lots this far from the center are kept even when unused
+const KEPT_LOTS: i32 = BORDER as i32 + 1;
+/// Seconds an unused chunk outside of the map and off screen is kept
+const CHUNK_LIFETIME: f64 = 30.0;
+
+/// Terrain of the cells of a lot, with one bit per cell for obstacles and water
+pub struct Chunk {
+    blocked: [u64; CHUNK_WORDS],
+    water: [u64; CHUNK_WORDS],
+    ground: Vec<GroundCell>,
+    /// Seconds since startup when the chunk was last needed
+    last_used: f64,
+}
+
+impl Default for Chunk {
+    fn default() -> Self {
+        Chunk {
+            blocked: [0; CHUNK_WORDS],
+            water: [0; CHUNK_WORDS],
+            ground: vec![
+                GroundCell {
+                    gradient: Vec2::ZERO,
+                    moisture: 0.0,
+                    biome: 0,
+                    movement_cost: 1.0,
+                };
+                CHUNK_CELLS
+            ],
+            last_used: 0.0,
+        }
+    }
+}
+
+impl Chunk {
+    fn bit(bits: &[u64; CHUNK_WORDS], index: usize) -> bool {
+        bits[index / 64] & (1 << (index % 64)) != 0
+    }
+
+    fn set_bit(bits: &mut [u64; CHUNK_WORDS], index: usize, value: bool) {
+        if value {
+            bits[index / 64] |= 1 << (index % 64);
+        } else {
+            bits[index / 64] &= !(1 << (index % 64));
+        }
+    }
+
+    fn set(&mut self, index: usize, blocked: bool, water: bool, ground: GroundCell) {
+        Self::set_bit(&mut self.blocked, index, blocked);
+        Self::set_bit(&mut self.water, index, water);
+        self.ground[index] = ground;
+    }
+}
+
 #[derive(Default)]
 pub struct ObstacleMap {
-    pub obstacle_map: HashMap<IVec2, bool>,
+    /// Generated terrain by lot, cells of lots not generated yet are blocked
+    chunks: HashMap<IVec2, Chunk>,
+    /// Water cells made passable by bridges and rafts, with the number of crossings over each
+    crossings: HashMap<IVec2, u32>,
+    /// Obstacles put down by the player, kept apart as they don't survive a restart, with the
+    /// number of obstacles covering each cell so that removing one keeps the others
+    placed: HashMap<IVec2, u32>,
+}
+
+impl ObstacleMap {
+    fn cell(x: f32, z: f32) -> IVec2 {
+        IVec2::new(
+            (x * DEF + DEF / 2.0).floor() as i32,
+            (z * DEF + DEF / 2.0).floor() as i32,
+        )
+    }
+
+    /// Lot of a cell and index of the cell in its chunk
+    fn locate(cell: IVec2) -> (IVec2, usize) {
+        let lot = IVec2::new(cell.x.div_euclid(CHUNK_SIDE), cell.y.div_euclid(CHUNK_SIDE));
+        let local = cell - lot * CHUNK_SIDE;
+        (lot, (local.x * CHUNK_SIDE + local.y) as usize)
+    }
+
+    fn chunk_cell(&self, cell: IVec2) -> Option<(&Chunk, usize)> {
+        let (lot, index) = Self::locate(cell);
+        self.chunks.get(&lot).map(|chunk| (chunk, index))
+    }
+
+    fn has_chunk(&self, lot: IVec2) -> bool {
+        self.chunks.contains_key(&lot)
+    }
+
+    fn touch_chunk(&mut self, lot: IVec2, now: f64) {
+        if let Some(chunk) = self.chunks.get_mut(&lot) {
+            chunk.last_used = now;
+        }
+    }
+
+    /// Forgets unused chunks away from the map, they are generated again when needed
+    fn evict_chunks(&mut self, now: f64, is_visible: impl Fn(IVec2) -> bool) {
+        let before = self.chunks.len();
+        self.chunks.retain(|lot, chunk| {
+            (lot.x.abs() <= KEPT_LOTS && lot.y.abs() <= KEPT_LOTS)
+                || is_visible(*lot)
+                || now - chunk.last_used < CHUNK_LIFETIME
+        });
+        if self.chunks.len() < before {
+            debug!("evicted {} terrain chunks", before - self.chunks.len());
+        }
+    }
+
+    /// Whether ants can dig through the cell at a world position: blocked by the terrain
+    /// itself, inside the map
+    pub fn is_diggable(&self, x: f32, z: f32) -> bool {
+        let cell = Self::cell(x, z);
+        (-BORDER..BORDER).contains(&x)
+            && (-BORDER..BORDER).contains(&z)
+            && !self.placed.contains_key(&cell)
+            && !self.is_water_cell(cell)
+            && self
+                .chunk_cell(cell)
+                .map_or(false, |(chunk, index)| Chunk::bit(&chunk.blocked, index))
+    }
+
+    fn dig(&mut self, cell: IVec2) {
+        let (lot, index) = Self::locate(cell);
+        if let Some(chunk) = self.chunks.get_mut(&lot) {
+            Chunk::set_bit(&mut chunk.blocked, index, false);
+        }
+    }
+
+    fn is_water_cell(&self, cell: IVec2) -> bool {
+        self.chunk_cell(cell)
+            .map_or(false, |(chunk, index)| Chunk::bit(&chunk.water, index))
+    }
+
+    /// World position of the center of a cell
+    fn cell_center(cell: IVec2) -> Vec2 {
+        (cell.as_vec2() + Vec2::splat(0.5)) / DEF - Vec2::splat(0.5)
+    }
+
+    fn is_blocked(&self, cell: IVec2) -> bool {
+        self.placed.contains_key(&cell)
+            || (self
+                .chunk_cell(cell)
+                .map_or(true, |(chunk, index)| Chunk::bit(&chunk.blocked, index))
+                && !self.crossings.contains_key(&cell))
+    }
+
+    /// Whether a circle of `radius` around a world position overlaps a blocked cell
+    pub fn is_obstacle(&self, x: f32, z: f32, radius: f32) -> bool {
+        if radius <= 0.0 {
+            return self.is_blocked(Self::cell(x, z));
+        }
+        let (low, high) = (
+            Self::cell(x - radius, z - radius),
+            Self::cell(x + radius, z + radius),
+        );
+        let half_cell = Vec2::splat(0.5 / DEF);
+        (low.x..=high.x)
+            .flat_map(|i| (low.y..=high.y).map(move |j| IVec2::new(i, j)))
+            .filter(|cell| self.is_blocked(*cell))
+            .any(|cell| {
+                // closest point of the cell to the center of the circle
+                let center = Self::cell_center(cell);
+                let closest = Vec2::new(x, z).clamp(center - half_cell, center + half_cell);
+                closest.distance_squared(Vec2::new(x, z)) < radius.powf(2.0)
+            })
+    }
+
+    /// Whether a circle of `radius` can slide from `from` to `to` without overlapping a
+    /// blocked cell
+    pub fn is_path_clear(&self, from: Vec2, to: Vec2, radius: f32) -> bool {
+        // a quarter of a cell between two checks never steps over a corner
+        let steps = (from.distance(to) * DEF * 4.0).ceil().max(1.0) as usize;
+        (0..=steps)
+            .map(|step| from.lerp(to, step as f32 / steps as f32))
+            .all(|point| !self.is_obstacle(point.x, point.y, radius))
+    }
+
+    /// Center of the closest cell, at most `max_distance` away from a world position, where a
+    /// circle of `radius` fits
+    pub fn nearest_free(&self, x: f32, z: f32, radius: f32, max_distance: f32) -> Option<Vec2> {
+        let origin = Vec2::new(x, z);
+        let (low, high) = (
+            Self::cell(x - max_distance, z - max_distance),
+            Self::cell(x + max_distance, z + max_distance),
+        );
+        (low.x..=high.x)
+            .flat_map(|i| (low.y..=high.y).map(move |j| IVec2::new(i, j)))
+            .map(Self::cell_center)
+            .filter(|center| center.distance_squared(origin) <= max_distance.powf(2.0))
+            .filter(|center| !self.is_obstacle(center.x, center.y, radius))
+            .min_by(|a, b| {
+                a.distance_squared(origin)
+                    .partial_cmp(&b.distance_squared(origin))
+                    .unwrap()
+            })
+    }
+
+    pub fn is_water(&self, x: f32, z: f32) -> bool {
+        self.is_water_cell(Self::cell(x, z))
+    }
+
+    pub fn ground_at(&self, x: f32, z: f32) -> Option<&GroundCell> {
+        self.chunk_cell(Self::cell(x, z))
+            .map(|(chunk, index)| &chunk.ground[index])
+    }
+
+    /// Index in the `BiomeTable` of the biome at a world position, once its lot is generated
+    pub fn biome_at(&self, x: f32, z: f32) -> Option<usize> {
+        self.ground_at(x, z).map(|cell| cell.biome)
+    }
+
+    /// Multiplier of the speed of a creature at a world position going in `heading`
+    pub fn speed_factor(&self, x: f32, z: f32, heading: Vec3) -> f32 {
+        self.ground_at(x, z)
+            .map_or(1.0, |cell| cell.speed_factor(heading))
+    }
+
+    /// Of `heading` and a few turned from it, the one making the most progress along `heading`
+    /// over `reach` for the speed of the ground on the way, with a clear path for `radius`
+    pub fn easiest_heading(&self, x: f32, z: f32, heading: Vec3, reach: f32, radius: f32) -> Vec3 {
+        let from = Vec2::new(x, z);
+        let progress = |candidate: Vec3| {
+            let to = from + Vec2::new(candidate.x, candidate.z).normalize_or_zero() * reach;
+            if !self.is_path_clear(from, to, radius) {
+                return None;
+            }
+            let speed = (self.speed_factor(x, z, candidate)
+                + self.speed_factor(to.x, to.y, candidate))
+                / 2.0;
+            Some(candidate.dot(heading) * speed)
+        };
+        let mut easiest = (heading, progress(heading).unwrap_or(f32::MIN));
+        for turn in ground::DETOURS {
+            let candidate = Quat::from_rotation_y(turn).mul_vec3(heading);
+            match progress(candidate) {
+                Some(progress) if progress > easiest.1 => easiest = (candidate, progress),
+                _ => (),
+            }
+        }
+        easiest.0
+    }
+
+    fn cells_within(x: f32, z: f32, radius: f32) -> impl Iterator<Item = IVec2> {
+        let cells = (radius * DEF).ceil() as i32;
+        let center = Self::cell(x, z);
+        (-cells..=cells)
+            .flat_map(move |i| (-cells..=cells).map(move |j| IVec2::new(i, j)))
+            .filter(move |offset| (offset.dot(*offset) as f32) <= (radius * DEF).powf(2.0))
+            .map(move |offset| center + offset)
+    }
+
+    /// Block every cell within `radius` of a world position
+    pub fn place_obstacle(&mut self, x: f32, z: f32, radius: f32) {
+        for cell in Self::cells_within(x, z, radius) {
+            *self.placed.entry(cell).or_insert(0) += 1;
+        }
+    }
+
+    /// Undo a `place_obstacle` with the same arguments, cells under other obstacles stay blocked
+    pub fn remove_obstacle(&mut self, x: f32, z: f32, radius: f32) {
+        for cell in Self::cells_within(x, z, radius) {
+            Self::release(&mut self.placed, cell);
+        }
+    }
+
+    /// Let creatures cross the water within `radius` of a world position
+    pub fn place_crossing(&mut self, x: f32, z: f32, radius: f32) {
+        let water = Self::cells_within(x, z, radius)
+            .filter(|cell| self.is_water_cell(*cell))
+            .collect::<Vec<_>>();
+        for cell in water {
+            *self.crossings.entry(cell).or_insert(0) += 1;
+        }
+    }
+
+    /// Undo a `place_crossing` with the same arguments
+    pub fn remove_crossing(&mut self, x: f32, z: f32, radius: f32) {
+        for cell in Self::cells_within(x, z, radius) {
+            Self::release(&mut self.crossings, cell);
+        }
+    }
+
+    fn release(counts: &mut HashMap<IVec2, u32>, cell: IVec2) {
+        if let Some(count) = counts.get_mut(&cell) {
+            *count -= 1;
+            if *count == 0 {
+                counts.remove(&cell);
+            }
+        }
+    }
+
+    pub fn clear_placed(&mut self) {
+        self.placed.clear();
+        self.crossings.clear();
+    }
 }
 
+#[cfg(test)]
 impl ObstacleMap {
-    pub fn is_obstacle(&self, x: f32, z: f32, _width: f32) -> bool {
-        *self
-            .obstacle_map
-            .get(&IVec2::new(
-                (x * DEF + DEF / 2.0) as i32,
-                (z * DEF + DEF / 2.0) as i32,
-            ))
-            .unwrap_or(&false)
+    /// Flat ground without obstacles over the lots `lots` along both axes
+    pub fn open_ground(lots: std::ops::RangeInclusive<i32>) -> Self {
+        let mut map = ObstacleMap::default();
+        for x in lots.clone() {
+            for z in lots.clone() {
+                map.chunks.insert(IVec2::new(x, z), Chunk::default());
+            }
+        }
+        map
+    }
+}
+
+/// Changes made to the terrain during a game, applied over the noise when generating lots
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct TerrainEdits {
+    /// Cells ants dug through, lowered and no longer obstacles
+    dug: std::collections::HashSet<IVec2>,
+    /// Radius of the mound around the main hill, the initial flat area until it grows
+    mound: f32,
+    /// Share of the digging done in cells not dug through yet
+    #[serde(skip)]
+    digging: HashMap<IVec2, f32>,
+}
+
+impl TerrainEdits {
+    fn mound_radius(&self) -> f32 {
+        self.mound.max(mound::MIN_RADIUS)
     }
 }
 
+/// Edits the next world starts from, the ones of a saved game or none for a new one
+#[derive(Default)]
+pub struct StartingEdits(pub TerrainEdits);
+
+mod mound {
+    /// Radius of the flat area the colony starts on
+    pub const MIN_RADIUS: f32 = 0.2236;
+    pub const MAX_RADIUS: f32 = 0.45;
+    pub const RADIUS_PER_ANT: f32 = 0.002;
+    /// Lots under the mound are meshed again each time it grows this much
+    pub const STEP: f32 = 0.02;
+    /// Height of the top of the mound above the flat area, per radius grown
+    pub const SLOPE: f32 = 0.1;
+    /// Height of the flat area
+    pub const BASE: f32 = 0.005;
+}
+
+mod dig {
+    /// Seconds of digging to get through a cell, for any number of ants
+    pub const EFFORT: f32 = 20.0;
+    /// Height of the ground in dug cells
+    pub const TUNNEL_FLOOR: f32 = 0.0;
+}
+
+#[derive(Clone)]
 pub struct NoiseSeeds {
+    /// Seed the others are drawn from, enough to build the same world again
+    seed: u64,
     elevation: u64,
     moisture: u64,
+    river: u64,
+    /// Terrain laid out by a scenario, over the noise
+    layout: Option<Arc<TerrainLayout>>,
+    /// Rivers, from the center of a pond to the center of the closest other one
+    channels: Arc<Vec<(Vec2, Vec2)>>,
+}
+
+/// Elevation read from a grayscale image covering the map, from -1.0 (black) to 1.0 (white)
+pub struct Heightmap {
+    width: u32,
+    height: u32,
+    values: Vec<f32>,
+}
+
+impl Heightmap {
+    pub fn load(path: &Path) -> Result<Self, image::ImageError> {
+        let image = image::open(path)?.into_luma8();
+        Ok(Heightmap {
+            width: image.width(),
+            height: image.height(),
+            values: image
+                .pixels()
+                .map(|pixel| pixel.0[0] as f32 / 255.0 * 2.0 - 1.0)
+                .collect(),
+        })
+    }
+
+    /// Bilinear sample, the image stretched from -BORDER to BORDER along x and z
+    fn sample(&self, x: f32, z: f32) -> Option<f32> {
+        if !(-BORDER..=BORDER).contains(&x) || !(-BORDER..=BORDER).contains(&z) {
+            return None;
+        }
+        let u = (x + BORDER) / (2.0 * BORDER) * (self.width - 1) as f32;
+        let v = (z + BORDER) / (2.0 * BORDER) * (self.height - 1) as f32;
+        let (u0, v0) = (u.floor() as u32, v.floor() as u32);
+        let (u1, v1) = ((u0 + 1).min(self.width - 1), (v0 + 1).min(self.height - 1));
+        let at = |u: u32, v: u32| self.values[(v * self.width + u) as usize];
+        let (du, dv) = (u.fract(), v.fract());
+        let top = at(u0, v0) + (at(u1, v0) - at(u0, v0)) * du;
+        let bottom = at(u0, v1) + (at(u1, v1) - at(u0, v1)) * du;
+        Some(top + (bottom - top) * dv)
+    }
+}
+
+/// Terrain set by a scenario: elevation from an image or disks over the noise, and where
+/// the main hill stands
+#[derive(Default)]
+pub struct TerrainLayout {
+    pub heightmap: Option<Heightmap>,
+    pub overrides: Vec<TerrainOverride>,
+    pub hill: Vec2,
+}
+
+impl TerrainLayout {
+    /// Elevation the layout sets at a world position, the last override covering it first
+    fn elevation(&self, x: f32, z: f32) -> Option<f32> {
+        self.overrides
+            .iter()
+            .rev()
+            .find(|disk| {
+                Vec2::new(x, z).distance_squared(Vec2::new(disk.center.0, disk.center.1))
+                    < disk.radius.powf(2.0)
+            })
+            .map(|disk| disk.elevation)
+            .or_else(|| {
+                self.heightmap
+                    .as_ref()
+                    .and_then(|heightmap| heightmap.sample(x, z))
+            })
+    }
+}
+
+impl NoiseSeeds {
+    /// Seeds for a new world, drawn from `seed`, or from a random one
+    pub fn new(seed: Option<u64>, layout: Option<TerrainLayout>) -> Self {
+        let seed = seed.unwrap_or_else(|| rand::thread_rng().gen());
+        info!("world seed {}", seed);
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut seeds = NoiseSeeds {
+            seed,
+            elevation: rng.gen(),
+            moisture: rng.gen(),
+            river: rng.gen(),
+            layout: layout.map(Arc::new),
+            channels: Arc::default(),
+        };
+        seeds.channels = Arc::new(seeds.terrain_noise().channels());
+        seeds
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Where the main hill stands
+    pub fn hill(&self) -> Vec2 {
+        self.layout
+            .as_ref()
+            .map_or(Vec2::ZERO, |layout| layout.hill)
+    }
+
+    fn elevation_noise(&self) -> FastNoise {
+        let mut elevation_noise = FastNoise::seeded(self.elevation);
+        elevation_noise.set_noise_type(NoiseType::PerlinFractal);
+        elevation_noise.set_fractal_type(FractalType::FBM);
+        elevation_noise.set_fractal_octaves(7);
+        elevation_noise.set_fractal_gain(0.4);
+        elevation_noise.set_fractal_lacunarity(2.0);
+        elevation_noise.set_frequency(2.0);
+        elevation_noise
+    }
+
+    fn moisture_noise(&self) -> FastNoise {
+        let mut moisture_noise = FastNoise::seeded(self.moisture);
+        moisture_noise.set_noise_type(NoiseType::PerlinFractal);
+        moisture_noise.set_fractal_type(FractalType::FBM);
+        moisture_noise.set_fractal_octaves(5);
+        moisture_noise.set_fractal_gain(0.75);
+        moisture_noise.set_fractal_lacunarity(2.0);
+        moisture_noise.set_frequency(2.0);
+        moisture_noise
+    }
+
+    fn river_noise(&self) -> FastNoise {
+        let mut river_noise = FastNoise::seeded(self.river);
+        river_noise.set_noise_type(NoiseType::PerlinFractal);
+        river_noise.set_fractal_type(FractalType::FBM);
+        river_noise.set_fractal_octaves(3);
+        river_noise.set_fractal_gain(0.3);
+        river_noise.set_fractal_lacunarity(2.0);
+        river_noise.set_frequency(0.8);
+        river_noise
+    }
+
+    fn terrain_noise(&self) -> TerrainNoise {
+        TerrainNoise {
+            elevation: self.elevation_noise(),
+            moisture: self.moisture_noise(),
+            river: self.river_noise(),
+            layout: self.layout.clone(),
+            channels: self.channels.clone(),
+            mound: mound::MIN_RADIUS,
+        }
+    }
+
+    /// Height of the terrain mesh at a world position with the `edits` of the game, without
+    /// building the noise each time
+    pub fn heightfield<'a>(&self, edits: &'a TerrainEdits) -> impl Fn(f32, f32) -> f32 + 'a {
+        let terrain_noise = TerrainNoise {
+            mound: edits.mound_radius(),
+            ..self.terrain_noise()
+        };
+        move |x, z| {
+            let height = terrain_noise.sample(x, z).1;
+            // the vertex closest to the position is the one of a cell of the same index
+            let vertex = ((Vec2::new(x, z) + 0.5) * DEF).round();
+            if edits.dug.contains(&vertex.as_ivec2()) {
+                height.min(dig::TUNNEL_FLOOR)
+            } else {
+                height
+            }
+        }
+    }
+
+    /// Moisture at a world position, from 0.0 (arid) to 1.0 (wet), as used to color the terrain
+    pub fn moisture_at(&self, x: f32, z: f32) -> f32 {
+        (self.moisture_noise().get_noise(x + 0.5, z + 0.5) + 0.5).clamp(0.0, 1.0)
+    }
 }
 
 impl Plugin for TerrainSpawnerPlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource(NoiseSeeds {
-            elevation: rand::thread_rng().gen(),
-            moisture: rand::thread_rng().gen(),
-        })
-        .init_resource::<ObstacleMap>()
-        .add_system_set(
-            SystemSet::on_update(GameState::Playing)
-                .with_system(fill_empty_lots)
-                .with_system(cleanup_lots),
-        );
+        app.insert_resource(BiomeTable::load())
+            .insert_resource(NoiseSeeds::new(None, None))
+            .init_resource::<ObstacleMap>()
+            .init_resource::<LotCache>()
+            .init_resource::<TerrainEdits>()
+            .init_resource::<StartingEdits>()
+            .init_resource::<PlaceholderHandles>()
+            .add_system_set(
+                SystemSet::on_update(GameState::Playing)
+                    .with_system(fill_empty_lots)
+                    .with_system(generate_lots)
+                    .with_system(update_lot_details)
+                    .with_system(dig_tunnels)
+                    .with_system(grow_mound)
+                    .with_system(cleanup_lots)
+                    .with_system(evict_chunks.config(|(_, _, _, timer)| {
+                        *timer = Some(Timer::new(Duration::from_secs_f32(5.0), true));
+                    })),
+            )
+            .add_system_set(SystemSet::on_exit(GameState::Menu).with_system(load_world))
+            .add_system_set(SystemSet::on_enter(GameState::Playing).with_system(reset_terrain));
     }
 }
 
 struct Lot {
-    mesh: bevy::render::mesh::Mesh,
+    vertices: Vec<Node>,
     color: bevy::render::texture::Image,
     metallic_roughness: bevy::render::texture::Image,
-    obstacle_map: HashMap<IVec2, bool>,
+    chunk: Chunk,
+    water_mesh: Option<bevy::render::mesh::Mesh>,
 }
 
 struct HandledLot {
+    /// Kept to mesh the lot again when its level of detail changes
+    vertices: Vec<Node>,
+    details: Details,
     mesh: Handle<bevy::render::mesh::Mesh>,
     color: Handle<bevy::pbr::StandardMaterial>,
+    water: Option<(
+        Handle<bevy::render::mesh::Mesh>,
+        Handle<bevy::pbr::StandardMaterial>,
+    )>,
+}
+
+/// Lots generated at most at once in the background
+const MAX_LOT_TASKS: usize = 4;
+
+/// Terrain of lots ready to be shown, and of lots on their way
+#[derive(Default)]
+struct LotCache {
+    handles: HashMap<IVec2, HandledLot>,
+    /// Lots waiting for a free task, the closest to the camera go first
+    queued: HashSet<IVec2>,
+    tasks: HashMap<IVec2, Task<Lot>>,
+    /// Lots whose terrain was edited, generated again even though they are ready
+    edited: HashSet<IVec2>,
+}
+
+impl LotCache {
+    fn refresh(&mut self, lot: IVec2) {
+        self.edited.insert(lot);
+        self.queued.insert(lot);
+    }
+
+    fn is_ready(&self, lot: IVec2, obstacle_map: &ObstacleMap) -> bool {
+        // evicted chunks are generated again, their mesh may still be cached
+        self.handles.contains_key(&lot) && obstacle_map.has_chunk(lot)
+    }
+}
+
+/// Flat tile shown on a lot until its terrain is generated
+#[derive(Component)]
+struct Placeholder(Entity);
+
+struct PlaceholderHandles {
+    mesh: Handle<bevy::render::mesh::Mesh>,
+    color: Handle<bevy::pbr::StandardMaterial>,
+}
+
+impl FromWorld for PlaceholderHandles {
+    fn from_world(world: &mut World) -> Self {
+        let mut meshes = world
+            .get_resource_mut::<Assets<bevy::render::mesh::Mesh>>()
+            .unwrap();
+        let mesh = meshes.add(bevy::render::mesh::Mesh::from(
+            bevy::render::mesh::shape::Plane { size: 1.0 },
+        ));
+        let mut materials = world
+            .get_resource_mut::<Assets<bevy::pbr::StandardMaterial>>()
+            .unwrap();
+        let color = materials.add(bevy::pbr::StandardMaterial {
+            base_color: bevy::render::color::Color::rgb(0.45, 0.6, 0.3),
+            perceptual_roughness: 1.0,
+            ..Default::default()
+        });
+        Self { mesh, color }
+    }
 }
 
 fn fill_empty_lots(
     mut commands: Commands,
-    lots: Query<(Entity, &EmptyLot)>,
-    (mut meshes, mut textures, mut materials): (
-        ResMut<Assets<bevy::render::mesh::Mesh>>,
-        ResMut<Assets<bevy::render::texture::Image>>,
-        ResMut<Assets<bevy::pbr::StandardMaterial>>,
-    ),
-    mut mesh_cache: Local<HashMap<IVec2, HandledLot>>,
+    lots: Query<(Entity, &EmptyLot, Option<&Placeholder>)>,
+    mut cache: ResMut<LotCache>,
     mut obstacle_map: ResMut<ObstacleMap>,
-    noise_seeds: Res<NoiseSeeds>,
+    placeholder: Res<PlaceholderHandles>,
+    time: Res<Time>,
 ) {
-    for (entity, position) in lots.iter() {
-        let mesh = mesh_cache
-            .entry(IVec2::new(position.x, position.z))
-            .or_insert_with(|| {
-                let lot = generate_lot(position.x, position.z, &*noise_seeds);
-                obstacle_map.obstacle_map.extend(lot.obstacle_map);
-                HandledLot {
-                    mesh: meshes.add(lot.mesh),
-                    color: materials.add(bevy::pbr::StandardMaterial {
-                        base_color: bevy::render::color::Color::WHITE,
-                        base_color_texture: Some(textures.add(lot.color)),
-                        perceptual_roughness: 1.0,
-                        metallic: 1.0,
-                        metallic_roughness_texture: Some(textures.add(lot.metallic_roughness)),
-                        ..Default::default()
-                    }),
-                }
-            });
+    for (entity, position, shown) in lots.iter() {
+        let lot_position = IVec2::new(position.x, position.z);
+        obstacle_map.touch_chunk(lot_position, time.seconds_since_startup());
+        if !cache.is_ready(lot_position, &*obstacle_map) {
+            if !cache.tasks.contains_key(&lot_position) {
+                cache.queued.insert(lot_position);
+            }
+            if position.offscreen {
+                commands.entity(entity).remove::<EmptyLot>();
+            } else if shown.is_none() {
+                let mut tile = None;
+                commands.entity(entity).with_children(|lot| {
+                    tile = Some(
+                        lot.spawn_bundle(bevy::pbr::PbrBundle {
+                            mesh: placeholder.mesh.clone_weak(),
+                            material: placeholder.color.clone_weak(),
+                            ..Default::default()
+                        })
+                        .insert(NotShadowCaster)
+                        .id(),
+                    );
+                });
+                commands.entity(entity).insert(Placeholder(tile.unwrap()));
+            }
+            continue;
+        }
+        let mesh = &cache.handles[&lot_position];
         if !position.offscreen {
+            if let Some(Placeholder(tile)) = shown {
+                commands.entity(*tile).despawn_recursive();
+            }
             commands
                 .entity(entity)
                 .with_children(|lot| {
@@ -122,11 +760,20 @@ fn fill_empty_lots(
                         ..Default::default()
                     })
                     .insert(NotShadowCaster);
+                    if let Some((water_mesh, water_color)) = &mesh.water {
+                        lot.spawn_bundle(bevy::pbr::PbrBundle {
+                            mesh: water_mesh.clone_weak(),
+                            material: water_color.clone_weak(),
+                            ..Default::default()
+                        })
+                        .insert(NotShadowCaster);
+                    }
                     // .insert_bundle((
                     //     BoundVol { sphere: None },
                     //     RayCastMesh::<crate::RaycastCameraToGround>::default(),
                     // ));
                 })
+                .remove::<Placeholder>()
                 .remove::<EmptyLot>();
         } else {
             commands.entity(entity).remove::<EmptyLot>();
@@ -134,72 +781,473 @@ fn fill_empty_lots(
     }
 }
 
-fn generate_lot(x: i32, z: i32, noise_seeds: &NoiseSeeds) -> Lot {
-    debug!("generating mesh for {} / {}", x, z);
-    let mut elevation_noise = FastNoise::seeded(noise_seeds.elevation);
-    elevation_noise.set_noise_type(NoiseType::PerlinFractal);
-    elevation_noise.set_fractal_type(FractalType::FBM);
-    elevation_noise.set_fractal_octaves(7);
-    elevation_noise.set_fractal_gain(0.4);
-    elevation_noise.set_fractal_lacunarity(2.0);
-    elevation_noise.set_frequency(2.0);
-
-    let mut moisture_noise = FastNoise::seeded(noise_seeds.moisture);
-    moisture_noise.set_noise_type(NoiseType::PerlinFractal);
-    moisture_noise.set_fractal_type(FractalType::FBM);
-    moisture_noise.set_fractal_octaves(5);
-    moisture_noise.set_fractal_gain(0.75);
-    moisture_noise.set_fractal_lacunarity(2.0);
-    moisture_noise.set_frequency(2.0);
-
-    const fn color_to_vec3(color: bevy::render::color::Color) -> Vec3 {
-        if let bevy::render::color::Color::Rgba {
-            red,
-            green,
-            blue,
-            alpha: _,
-        } = color
+/// Collects the lots generated in the background, and starts generating the closest queued ones
+#[allow(clippy::too_many_arguments)]
+fn generate_lots(
+    mut cache: ResMut<LotCache>,
+    mut obstacle_map: ResMut<ObstacleMap>,
+    (mut meshes, mut textures, mut materials): (
+        ResMut<Assets<bevy::render::mesh::Mesh>>,
+        ResMut<Assets<bevy::render::texture::Image>>,
+        ResMut<Assets<bevy::pbr::StandardMaterial>>,
+    ),
+    noise_seeds: Res<NoiseSeeds>,
+    biomes: Res<BiomeTable>,
+    edits: Res<TerrainEdits>,
+    camera: Query<&GlobalTransform, With<bevy::render::camera::PerspectiveProjection>>,
+    task_pool: Res<AsyncComputeTaskPool>,
+) {
+    let cache = &mut *cache;
+    let camera = camera
+        .get_single()
+        .map(|transform| transform.translation)
+        .unwrap_or(Vec3::ZERO);
+    let generated = cache
+        .tasks
+        .iter_mut()
+        .filter_map(|(position, task)| {
+            future::block_on(future::poll_once(task)).map(|lot| (*position, lot))
+        })
+        .collect::<Vec<_>>();
+    for (position, mut lot) in generated {
+        cache.tasks.remove(&position);
+        obstacle_map
+            .chunks
+            .insert(position, std::mem::take(&mut lot.chunk));
+        // cells dug while the lot was generated
+        for cell in edits.dug.iter() {
+            if ObstacleMap::locate(*cell).0 == position {
+                obstacle_map.dig(*cell);
+            }
+        }
+        if let Some(handled) = cache.handles.get_mut(&position) {
+            // the lot was edited, its mesh is replaced where it is shown
+            if let Some(mesh) = meshes.get_mut(&handled.mesh) {
+                *mesh = vertices_as_mesh(&lot.vertices, DEF as u32, handled.details);
+            }
+            handled.vertices = lot.vertices;
+            continue;
+        }
+        cache.handles.entry(position).or_insert_with(|| {
+            let water = lot.water_mesh.map(|water_mesh| {
+                (
+                    meshes.add(water_mesh),
+                    materials.add(bevy::pbr::StandardMaterial {
+                        base_color: bevy::render::color::Color::rgba(0.2, 0.4, 0.7, 0.7),
+                        alpha_mode: bevy::pbr::AlphaMode::Blend,
+                        perceptual_roughness: 0.1,
+                        ..Default::default()
+                    }),
+                )
+            });
+            let details = lot_details(position, camera);
+            HandledLot {
+                water,
+                mesh: meshes.add(vertices_as_mesh(&lot.vertices, DEF as u32, details)),
+                vertices: lot.vertices,
+                details,
+                color: materials.add(bevy::pbr::StandardMaterial {
+                    base_color: bevy::render::color::Color::WHITE,
+                    base_color_texture: Some(textures.add(lot.color)),
+                    perceptual_roughness: 1.0,
+                    metallic: 1.0,
+                    metallic_roughness_texture: Some(textures.add(lot.metallic_roughness)),
+                    ..Default::default()
+                }),
+            }
+        });
+    }
+
+    let free = MAX_LOT_TASKS.saturating_sub(cache.tasks.len());
+    if free == 0 || cache.queued.is_empty() {
+        return;
+    }
+    let distance = |lot: &IVec2| {
+        lot.as_vec2()
+            .distance_squared(Vec2::new(camera.x, camera.z))
+    };
+    let mut queued = cache.queued.drain().collect::<Vec<_>>();
+    queued.retain(|lot| !cache.is_ready(*lot, &*obstacle_map) || cache.edited.contains(lot));
+    queued.sort_by(|a, b| {
+        distance(a)
+            .partial_cmp(&distance(b))
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    let mut started = 0;
+    for lot in queued {
+        // lots edited while being generated wait for their task to finish
+        if started == free || cache.tasks.contains_key(&lot) {
+            cache.queued.insert(lot);
+            continue;
+        }
+        let (noise_seeds, biomes, edits) = (noise_seeds.clone(), biomes.clone(), edits.clone());
+        let task = task_pool
+            .spawn(async move { generate_lot(lot.x, lot.y, &noise_seeds, &biomes, &edits) });
+        cache.tasks.insert(lot, task);
+        cache.edited.remove(&lot);
+        started += 1;
+    }
+}
+
+/// Ants blocked by the terrain slowly dig through it
+fn dig_tunnels(
+    mut events: EventReader<WorldEvents>,
+    mut edits: ResMut<TerrainEdits>,
+    mut obstacle_map: ResMut<ObstacleMap>,
+    mut cache: ResMut<LotCache>,
+    time: Res<Time>,
+) {
+    // a cell is dug at the same pace however many ants push against it
+    let cells = events
+        .iter()
+        .filter_map(|event| match event {
+            WorldEvents::Dig(position) if obstacle_map.is_diggable(position.x, position.z) => {
+                Some(ObstacleMap::cell(position.x, position.z))
+            }
+            _ => None,
+        })
+        .collect::<HashSet<_>>();
+    let mut lots = HashSet::default();
+    for cell in cells {
+        let dug = edits.digging.entry(cell).or_insert(0.0);
+        *dug += time.delta_seconds() / dig::EFFORT;
+        if *dug < 1.0 {
+            continue;
+        }
+        edits.digging.remove(&cell);
+        edits.dug.insert(cell);
+        obstacle_map.dig(cell);
+        // lots before the cell share its corner
+        lots.extend(
+            [IVec2::ZERO, IVec2::X, IVec2::Y, IVec2::ONE]
+                .iter()
+                .map(|offset| ObstacleMap::locate(cell - *offset).0),
+        );
+    }
+    for lot in lots {
+        cache.refresh(lot);
+    }
+}
+
+/// The mound around the main hill grows with the colony
+fn grow_mound(
+    ants: Query<&Creature>,
+    mut edits: ResMut<TerrainEdits>,
+    mut cache: ResMut<LotCache>,
+    noise_seeds: Res<NoiseSeeds>,
+) {
+    let ants = ants
+        .iter()
+        .filter(|ant| ant.colony == Colony::PLAYER)
+        .count();
+    let radius = (mound::MIN_RADIUS + ants as f32 * mound::RADIUS_PER_ANT).min(mound::MAX_RADIUS);
+    if radius >= edits.mound_radius() + mound::STEP {
+        edits.mound = radius;
+        // lots are centered on their position
+        let hill = noise_seeds.hill() + Vec2::splat(0.5);
+        let (from, to) = ((hill - radius).floor(), (hill + radius).floor());
+        for x in from.x as i32..=to.x as i32 {
+            for z in from.y as i32..=to.y as i32 {
+                cache.refresh(IVec2::new(x, z));
+            }
+        }
+    }
+}
+
+/// Forgets the previous world and generates the terrain of the map for the one picked in the
+/// menu, so that everything placed when the game starts sees it; meshes follow in the background
+fn load_world(
+    mut edits: ResMut<TerrainEdits>,
+    starting_edits: Res<StartingEdits>,
+    mut cache: ResMut<LotCache>,
+    mut obstacle_map: ResMut<ObstacleMap>,
+    noise_seeds: Res<NoiseSeeds>,
+    biomes: Res<BiomeTable>,
+    time: Res<Time>,
+) {
+    *edits = starting_edits.0.clone();
+    *cache = LotCache::default();
+    obstacle_map.chunks.clear();
+    let lots = BORDER as i32;
+    for x in -lots..=lots {
+        for z in -lots..=lots {
+            let mut chunk = generate_lot(x, z, &*noise_seeds, &*biomes, &*edits).chunk;
+            chunk.last_used = time.seconds_since_startup();
+            obstacle_map.chunks.insert(IVec2::new(x, z), chunk);
+        }
+    }
+}
+
+/// Starts a new game of the same world on the terrain it was loaded with
+fn reset_terrain(
+    mut edits: ResMut<TerrainEdits>,
+    starting_edits: Res<StartingEdits>,
+    mut cache: ResMut<LotCache>,
+) {
+    let unchanged = edits.dug == starting_edits.0.dug && edits.mound == starting_edits.0.mound;
+    *edits = starting_edits.0.clone();
+    if unchanged {
+        return;
+    }
+    let lots = cache.handles.keys().copied().collect::<Vec<_>>();
+    for lot in lots {
+        cache.refresh(lot);
+    }
+}
+
+/// Rebuilds the meshes of visible lots when the camera moved enough to change their
+/// level of detail, or the one of a neighbour
+fn update_lot_details(
+    mut cache: ResMut<LotCache>,
+    mut meshes: ResMut<Assets<bevy::render::mesh::Mesh>>,
+    visible_lots: Res<VisibleLots>,
+    camera: Query<&GlobalTransform, With<bevy::render::camera::PerspectiveProjection>>,
+) {
+    let camera = match camera.get_single() {
+        Ok(transform) => transform.translation,
+        Err(_) => return,
+    };
+    for (position, lot) in cache.handles.iter_mut() {
+        if !visible_lots.contains(*position) {
+            continue;
+        }
+        let details = lot_details(*position, camera);
+        if details != lot.details {
+            lot.details = details;
+            if let Some(mesh) = meshes.get_mut(&lot.mesh) {
+                *mesh = vertices_as_mesh(&lot.vertices, DEF as u32, details);
+            }
+        }
+    }
+}
+
+mod lod {
+    /// Vertices skipped along each side of a lot, from full detail to the coarsest level
+    pub const STEPS: [u32; 3] = [1, 2, 4];
+    /// Lots further than these from the camera use the next level
+    pub const DISTANCES: [f32; 2] = [5.0, 6.0];
+}
+
+/// Vertex step of a lot, and of its neighbours along -x, +x, -z and +z
+type Details = (u32, [u32; 4]);
+
+fn lod_step(lot: IVec2, camera: Vec3) -> u32 {
+    let distance = Vec3::new(lot.x as f32, 0.0, lot.y as f32).distance(camera);
+    lod::STEPS[lod::DISTANCES
+        .iter()
+        .filter(|threshold| distance > **threshold)
+        .count()]
+}
+
+fn lot_details(lot: IVec2, camera: Vec3) -> Details {
+    let step = |offset: IVec2| lod_step(lot + offset, camera);
+    (
+        step(IVec2::ZERO),
+        [
+            step(-IVec2::X),
+            step(IVec2::X),
+            step(-IVec2::Y),
+            step(IVec2::Y),
+        ],
+    )
+}
+
+/// Height of the surface of ponds and rivers
+pub const WATER_LEVEL: f32 = -0.004;
+
+mod water {
+    /// Basins lower than this and wetter than `POND_MOISTURE` hold ponds
+    pub const POND_ELEVATION: f32 = -0.2;
+    pub const POND_MOISTURE: f32 = 0.6;
+    /// Rivers run in channels between ponds, on ground lower than this
+    pub const RIVER_ELEVATION: f32 = 0.15;
+    /// Half of the width of a channel
+    pub const RIVER_WIDTH: f32 = 0.04;
+    /// How far the river noise pushes a channel off the straight line between its ponds
+    pub const MEANDER: f32 = 0.15;
+    /// Step of the grid ponds are searched on
+    pub const POND_SCAN: f32 = 0.05;
+    /// Pond cells closer than this to the first cell of a pond belong to it
+    pub const POND_SPREAD: f32 = 0.3;
+    /// Ponds further apart than this are not linked
+    pub const CHANNEL_LENGTH: f32 = 1.5;
+    /// Of the bed under the surface
+    pub const DEPTH: f32 = 0.006;
+    /// The ground stays dry this close to the first hill
+    pub const DRY_RADIUS: f32 = 0.3;
+}
+
+/// Noises the terrain is built from
+struct TerrainNoise {
+    elevation: FastNoise,
+    moisture: FastNoise,
+    river: FastNoise,
+    layout: Option<Arc<TerrainLayout>>,
+    channels: Arc<Vec<(Vec2, Vec2)>>,
+    /// Radius of the mound around the main hill
+    mound: f32,
+}
+
+impl TerrainNoise {
+    /// Raw elevation noise, terrain height and whether water covers a world position
+    fn sample(&self, x: f32, z: f32) -> (f32, f32, bool) {
+        let (elevation, height) = self.elevation_at(x, z);
+        if self.is_water(elevation, x, z) {
+            (elevation, height.min(WATER_LEVEL) - water::DEPTH, true)
+        } else {
+            (elevation, height, false)
+        }
+    }
+
+    fn hill(&self) -> Vec2 {
+        self.layout
+            .as_ref()
+            .map_or(Vec2::ZERO, |layout| layout.hill)
+    }
+
+    fn is_water(&self, elevation: f32, x: f32, z: f32) -> bool {
+        if Vec2::new(x, z).distance_squared(self.hill()) < water::DRY_RADIUS.powf(2.0)
+            || !(-BORDER..=BORDER).contains(&x)
+            || !(-BORDER..=BORDER).contains(&z)
         {
-            const_vec3!([red, green, blue])
+            return false;
+        }
+        let river = elevation < water::RIVER_ELEVATION && {
+            // the river noise bends the channels, the same way on both banks
+            let bent = Vec2::new(x, z)
+                + Vec2::new(
+                    self.river.get_noise(x, z),
+                    self.river.get_noise(x + 10.0, z + 10.0),
+                ) * water::MEANDER;
+            self.channels
+                .iter()
+                .any(|(from, to)| distance_to_segment(bent, *from, *to) < water::RIVER_WIDTH)
+        };
+        self.is_pond(elevation, x, z) || river
+    }
+
+    fn is_pond(&self, elevation: f32, x: f32, z: f32) -> bool {
+        elevation < water::POND_ELEVATION
+            && self.moisture.get_noise(x + 0.5, z + 0.5) + 0.5 > water::POND_MOISTURE
+    }
+
+    /// Rivers linking each pond of the map to the closest other one
+    fn channels(&self) -> Vec<(Vec2, Vec2)> {
+        // group the pond points of a coarse grid into ponds, as (first point, sum, count)
+        let steps = (2.0 * BORDER / water::POND_SCAN) as i32;
+        let mut ponds: Vec<(Vec2, Vec2, f32)> = vec![];
+        for point in (0..=steps).flat_map(|i| {
+            (0..=steps).map(move |j| {
+                Vec2::new(i as f32, j as f32) * water::POND_SCAN - Vec2::splat(BORDER)
+            })
+        }) {
+            if point.distance_squared(self.hill()) < water::DRY_RADIUS.powf(2.0)
+                || !self.is_pond(self.elevation_at(point.x, point.y).0, point.x, point.y)
+            {
+                continue;
+            }
+            match ponds
+                .iter_mut()
+                .find(|(first, _, _)| first.distance(point) < water::POND_SPREAD)
+            {
+                Some((_, sum, count)) => {
+                    *sum += point;
+                    *count += 1.0;
+                }
+                None => ponds.push((point, point, 1.0)),
+            }
+        }
+        let centers = ponds
+            .iter()
+            .map(|(_, sum, count)| *sum / *count)
+            .collect::<Vec<_>>();
+        let mut channels: Vec<(Vec2, Vec2)> = vec![];
+        for (i, center) in centers.iter().enumerate() {
+            let closest = centers
+                .iter()
+                .enumerate()
+                .filter(|(j, other)| *j != i && other.distance(*center) < water::CHANNEL_LENGTH)
+                .min_by(|(_, a), (_, b)| {
+                    a.distance_squared(*center)
+                        .partial_cmp(&b.distance_squared(*center))
+                        .unwrap()
+                });
+            if let Some((_, other)) = closest {
+                if !channels.contains(&(*other, *center)) {
+                    channels.push((*center, *other));
+                }
+            }
+        }
+        channels
+    }
+
+    /// Raw elevation noise and terrain height at a world position
+    fn elevation_at(&self, x: f32, z: f32) -> (f32, f32) {
+        let (distance, mound) = (
+            Vec2::new(x, z).distance_squared(self.hill()),
+            self.mound.powf(2.0),
+        );
+        if distance < mound {
+            let top = (self.mound - mound::MIN_RADIUS) * mound::SLOPE;
+            return (0.0, mound::BASE + top * (1.0 - distance / mound));
+        }
+        let elevation = self
+            .layout
+            .as_ref()
+            .and_then(|layout| layout.elevation(x, z))
+            .unwrap_or_else(|| self.elevation.get_noise(x, z));
+        if !(-BORDER..=BORDER).contains(&x) || !(-BORDER..=BORDER).contains(&z) {
+            (elevation + 0.4, 0.41 + elevation / 10.0)
         } else {
-            const_vec3!([0.0, 0.0, 0.0])
+            (
+                elevation,
+                elevation / 75.0 + if elevation > 0.95 { 0.4 } else { 0.0 },
+            )
         }
     }
-    let moisture_mountain = color_to_vec3(bevy::render::color::Color::ALICE_BLUE);
-    let moisture_prairie = color_to_vec3(bevy::render::color::Color::GREEN);
-    let arid_mountain = color_to_vec3(bevy::render::color::Color::ANTIQUE_WHITE);
-    let arid_prairie = color_to_vec3(bevy::render::color::Color::GRAY);
+}
+
+fn distance_to_segment(point: Vec2, from: Vec2, to: Vec2) -> f32 {
+    let along = to - from;
+    let t = ((point - from).dot(along) / along.length_squared().max(f32::EPSILON)).clamp(0.0, 1.0);
+    point.distance(from + along * t)
+}
+
+fn generate_lot(
+    x: i32,
+    z: i32,
+    noise_seeds: &NoiseSeeds,
+    biomes: &BiomeTable,
+    edits: &TerrainEdits,
+) -> Lot {
+    debug!("generating mesh for {} / {}", x, z);
+    let terrain_noise = TerrainNoise {
+        mound: edits.mound_radius(),
+        ..noise_seeds.terrain_noise()
+    };
 
     let mut vertices = Vec::new();
     let mut colors = Vec::new();
     let mut metallic_roughness = Vec::new();
 
-    let mut obstacle_map = HashMap::default();
+    let mut chunk = Chunk::default();
+    let mut water_points = Vec::new();
 
     for i in 0..=(DEF as i32) {
         for j in 0..=(DEF as i32) {
             let nx = x as f32 + i as f32 / DEF;
             let nz = z as f32 + j as f32 / DEF;
-            let get_elevation = |x: f32, z: f32, dx: f32, dz: f32| {
-                let px = x + dx - 0.5;
-                let pz = z + dz - 0.5;
-                if px.powf(2.0) + pz.powf(2.0) < 0.05 {
-                    (0.0, 0.005)
-                } else {
-                    let elevation = elevation_noise.get_noise(px, pz);
-                    if !(-BORDER..=BORDER).contains(&px) || !(-BORDER..=BORDER).contains(&pz) {
-                        (elevation + 0.4, 0.41 + elevation / 10.0)
-                    } else {
-                        (
-                            elevation,
-                            elevation / 75.0 + if elevation > 0.95 { 0.4 } else { 0.0 },
-                        )
-                    }
-                }
-            };
+            let get_elevation =
+                |x: f32, z: f32, dx: f32, dz: f32| terrain_noise.sample(x + dx - 0.5, z + dz - 0.5);
 
-            let (elevation, elevation_mod) =
+            let (elevation, mut elevation_mod, is_water) =
                 get_elevation(x as f32, z as f32, i as f32 / DEF, j as f32 / DEF);
+            let dug = edits
+                .dug
+                .contains(&IVec2::new(x * CHUNK_SIDE + i, z * CHUNK_SIDE + j));
+            if dug {
+                elevation_mod = elevation_mod.min(dig::TUNNEL_FLOOR);
+            }
 
             let mut neighbours = Vec::new();
             let mut has_obstacle_in_neighbours = false;
@@ -214,10 +1262,9 @@ fn generate_lot(x: i32, z: i32, noise_seeds: &NoiseSeeds) -> Lot {
                     }
                 }
             }
-            obstacle_map.insert(
-                IVec2::new(x * DEF as i32 + i, z * DEF as i32 + j),
-                elevation_mod > 0.4 || has_obstacle_in_neighbours,
-            );
+            if is_water {
+                water_points.push(Vec2::new(i as f32 / DEF - 0.5, j as f32 / DEF - 0.5));
+            }
 
             let mut normal = Vec3::ZERO;
             for (b, c) in [
@@ -249,22 +1296,44 @@ fn generate_lot(x: i32, z: i32, noise_seeds: &NoiseSeeds) -> Lot {
                 [j as f32 / DEF, i as f32 / DEF],
             ));
 
-            let moisture = moisture_noise.get_noise(nx, nz);
+            let moisture = terrain_noise.moisture.get_noise(nx, nz);
 
             let elevation = elevation + 0.5;
             let moisture = moisture + 0.5;
-            let mountain = arid_mountain.lerp(moisture_mountain, (moisture * 2.0).clamp(0.0, 1.0));
-            let prairie = arid_prairie.lerp(moisture_prairie, (moisture * 2.0).clamp(0.0, 1.0));
-            let lerped = prairie.lerp(mountain, elevation);
+
+            let biome_index = biomes.classify(elevation, moisture);
+            let biome = biomes.get(biome_index);
+            // the last row and column belong to the next lots
+            if i < CHUNK_SIDE && j < CHUNK_SIDE {
+                chunk.set(
+                    (i * CHUNK_SIDE + j) as usize,
+                    !dug && (elevation_mod > 0.4
+                        || has_obstacle_in_neighbours
+                        || is_water
+                        || !biome.passable),
+                    is_water,
+                    // neighbours go along z first, then along x
+                    GroundCell {
+                        gradient: Vec2::new(
+                            neighbours[6][1] - neighbours[1][1],
+                            neighbours[4][1] - neighbours[3][1],
+                        ) * DEF
+                            / 2.0,
+                        moisture: moisture.clamp(0.0, 1.0),
+                        biome: biome_index,
+                        movement_cost: biome.movement_cost,
+                    },
+                );
+            }
 
             colors.extend_from_slice(&[
-                (lerped.x * 255.0) as u8,
-                (lerped.y * 255.0) as u8,
-                (lerped.z * 255.0) as u8,
+                (biome.color[0] * 255.0) as u8,
+                (biome.color[1] * 255.0) as u8,
+                (biome.color[2] * 255.0) as u8,
                 255,
             ]);
 
-            let roughness = ((1.0 - elevation) * 2.0).clamp(0.0, 1.0);
+            let roughness = biome.roughness.clamp(0.0, 1.0);
             let metallic = 1.0 - moisture;
             metallic_roughness.extend_from_slice(&[
                 0,
@@ -276,7 +1345,7 @@ fn generate_lot(x: i32, z: i32, noise_seeds: &NoiseSeeds) -> Lot {
     }
 
     Lot {
-        mesh: vertices_as_mesh(vertices, DEF as u32),
+        vertices,
         color: bevy::render::texture::Image::new(
             bevy::render::render_resource::Extent3d {
                 width: DEF as u32 + 1,
@@ -297,12 +1366,125 @@ fn generate_lot(x: i32, z: i32, noise_seeds: &NoiseSeeds) -> Lot {
             metallic_roughness,
             bevy::render::render_resource::TextureFormat::Rgba8UnormSrgb,
         ),
-        obstacle_map,
+        chunk,
+        water_mesh: (!water_points.is_empty()).then(|| water_mesh(&water_points)),
     }
 }
+
+/// Writes the elevation, moisture, biome colors and obstacles of the lots from `from` to `to`
+/// as PNGs named after `prefix`, one pixel per cell, sampled as when playing with `edits`
+pub fn export_images(
+    noise_seeds: &NoiseSeeds,
+    biomes: &BiomeTable,
+    edits: &TerrainEdits,
+    from: IVec2,
+    to: IVec2,
+    prefix: &str,
+) -> Result<(), image::ImageError> {
+    let lots = (to - from + IVec2::ONE).max(IVec2::ONE);
+    let (width, height) = ((lots.x * CHUNK_SIDE) as u32, (lots.y * CHUNK_SIDE) as u32);
+    let pixels = (width * height) as usize;
+    let mut heights = vec![0.0; pixels];
+    let mut moisture = vec![0; pixels];
+    let mut colors = vec![0; pixels * 3];
+    let mut obstacles = vec![0; pixels * 3];
+    for x in 0..lots.x {
+        for z in 0..lots.y {
+            let lot = generate_lot(from.x + x, from.y + z, noise_seeds, biomes, edits);
+            for i in 0..CHUNK_SIDE {
+                for j in 0..CHUNK_SIDE {
+                    let index = (i * CHUNK_SIDE + j) as usize;
+                    let pixel = ((z * CHUNK_SIDE + j) as u32 * width + (x * CHUNK_SIDE + i) as u32)
+                        as usize;
+                    let ground = &lot.chunk.ground[index];
+                    heights[pixel] = lot.vertices[(i * (CHUNK_SIDE + 1) + j) as usize].0[1];
+                    moisture[pixel] = (ground.moisture * 255.0) as u8;
+                    let color = biomes.get(ground.biome).color;
+                    for channel in 0..3 {
+                        colors[pixel * 3 + channel] = (color[channel] * 255.0) as u8;
+                    }
+                    let obstacle = if Chunk::bit(&lot.chunk.water, index) {
+                        [40, 90, 200]
+                    } else if Chunk::bit(&lot.chunk.blocked, index) {
+                        [255, 255, 255]
+                    } else {
+                        [0, 0, 0]
+                    };
+                    obstacles[pixel * 3..pixel * 3 + 3].copy_from_slice(&obstacle);
+                }
+            }
+        }
+    }
+    // heights are stretched over the whole gray range of the region
+    let (low, high) = heights
+        .iter()
+        .fold((f32::MAX, f32::MIN), |(low, high), height| {
+            (low.min(*height), high.max(*height))
+        });
+    let elevation = heights
+        .iter()
+        .map(|height| ((height - low) / (high - low).max(f32::EPSILON) * 255.0) as u8)
+        .collect::<Vec<_>>();
+
+    for (name, buffer, color) in [
+        ("elevation", &elevation, image::ColorType::L8),
+        ("moisture", &moisture, image::ColorType::L8),
+        ("biomes", &colors, image::ColorType::Rgb8),
+        ("obstacles", &obstacles, image::ColorType::Rgb8),
+    ] {
+        image::save_buffer(
+            format!("{}-{}.png", prefix, name),
+            buffer,
+            width,
+            height,
+            color,
+        )?;
+    }
+    Ok(())
+}
+
+/// Flat squares at the water level centered on each point
+fn water_mesh(points: &[Vec2]) -> bevy::render::mesh::Mesh {
+    let half = 0.5 / DEF;
+    let mut positions = Vec::new();
+    let mut indices = Vec::new();
+    for point in points {
+        let n = positions.len() as u32;
+        for (dx, dz) in [(-half, -half), (half, -half), (-half, half), (half, half)] {
+            positions.push([point.x + dx, WATER_LEVEL, point.y + dz]);
+        }
+        indices.extend_from_slice(&[n, n + 2, n + 1, n + 2, n + 3, n + 1]);
+    }
+    let normals = vec![[0.0, 1.0, 0.0]; positions.len()];
+    let uvs = vec![[0.0, 0.0]; positions.len()];
+
+    let mut mesh = bevy::render::mesh::Mesh::new(
+        bevy::render::render_resource::PrimitiveTopology::TriangleList,
+    );
+    mesh.set_attribute(bevy::render::mesh::Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.set_attribute(bevy::render::mesh::Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.set_attribute(bevy::render::mesh::Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh.set_indices(Some(bevy::render::mesh::Indices::U32(indices)));
+    mesh
+}
 type Node = ([f32; 3], [f32; 3], [f32; 2]);
 
-fn vertices_as_mesh(vertices: Vec<Node>, details: u32) -> bevy::render::mesh::Mesh {
+fn lerp_node(from: Node, to: Node, t: f32) -> Node {
+    (
+        Vec3::from(from.0).lerp(Vec3::from(to.0), t).into(),
+        Vec3::from(from.1)
+            .lerp(Vec3::from(to.1), t)
+            .normalize()
+            .into(),
+        Vec2::from(from.2).lerp(Vec2::from(to.2), t).into(),
+    )
+}
+
+fn vertices_as_mesh(
+    vertices: &[Node],
+    details: u32,
+    (step, seams): Details,
+) -> bevy::render::mesh::Mesh {
     let mut positions = Vec::new();
     let mut normals = Vec::new();
     let mut uvs = Vec::new();
@@ -325,16 +1507,40 @@ fn vertices_as_mesh(vertices: Vec<Node>, details: u32) -> bevy::render::mesh::Me
         }
     };
 
-    for i in 0..details {
-        for j in 0..details {
-            let data1 = *vertices.get((i + j * (details + 1)) as usize).unwrap();
-            let data2 = *vertices.get((i + 1 + j * (details + 1)) as usize).unwrap();
-            let data3 = *vertices
-                .get((i + (j + 1) * (details + 1)) as usize)
-                .unwrap();
-            let data4 = *vertices
-                .get((i + 1 + (j + 1) * (details + 1)) as usize)
-                .unwrap();
+    let get = |i: u32, j: u32| vertices[(i + j * (details + 1)) as usize];
+    // on a side next to a coarser lot, vertices are moved on the edges of the coarser
+    // lot so that no cracks open between them
+    let node = |i: u32, j: u32| {
+        let (side, along) = if j == 0 {
+            (0, i)
+        } else if j == details {
+            (1, i)
+        } else if i == 0 {
+            (2, j)
+        } else if i == details {
+            (3, j)
+        } else {
+            return get(i, j);
+        };
+        let seam = seams[side].max(step);
+        let (low, offset) = (along - along % seam, along % seam);
+        if offset == 0 {
+            return get(i, j);
+        }
+        let (from, to) = if side < 2 {
+            (get(low, j), get(low + seam, j))
+        } else {
+            (get(i, low), get(i, low + seam))
+        };
+        lerp_node(from, to, offset as f32 / seam as f32)
+    };
+
+    for i in (0..details).step_by(step as usize) {
+        for j in (0..details).step_by(step as usize) {
+            let data1 = node(i, j);
+            let data2 = node(i + step, j);
+            let data3 = node(i, j + step);
+            let data4 = node(i + step, j + step);
 
             indices.extend_from_slice(&[push(data1), push(data2), push(data3)]);
             indices.extend_from_slice(&[push(data3), push(data2), push(data4)]);
@@ -359,3 +1565,165 @@ fn cleanup_lots(
         commands.entity(entity).despawn();
     }
 }
+
+fn evict_chunks(
+    mut obstacle_map: ResMut<ObstacleMap>,
+    visible_lots: Res<VisibleLots>,
+    time: Res<Time>,
+    mut timer: Local<Timer>,
+) {
+    if timer.tick(time.delta()).just_finished() {
+        obstacle_map.evict_chunks(time.seconds_since_startup(), |lot| {
+            visible_lots.contains(lot)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ANT: f32 = 0.015;
+    const ANTEATER: f32 = 0.075;
+
+    /// A map with the given cells blocked
+    fn map_with(cells: &[(i32, i32)]) -> ObstacleMap {
+        let mut map = ObstacleMap::default();
+        for (i, j) in cells {
+            set_cell(&mut map, IVec2::new(*i, *j), true, false);
+        }
+        map
+    }
+
+    fn set_cell(map: &mut ObstacleMap, cell: IVec2, blocked: bool, water: bool) {
+        let (lot, index) = ObstacleMap::locate(cell);
+        let chunk = map.chunks.entry(lot).or_insert_with(Chunk::default);
+        Chunk::set_bit(&mut chunk.blocked, index, blocked);
+        Chunk::set_bit(&mut chunk.water, index, water);
+    }
+
+    fn center(i: i32, j: i32) -> Vec2 {
+        ObstacleMap::cell_center(IVec2::new(i, j))
+    }
+
+    #[test]
+    fn cells_round_down_on_both_sides_of_zero() {
+        assert_eq!(ObstacleMap::cell(-0.5, -0.5), IVec2::new(0, 0));
+        assert_eq!(ObstacleMap::cell(-0.51, -0.49), IVec2::new(-1, 0));
+        assert_eq!(ObstacleMap::cell(-1.0, 1.0), IVec2::new(-10, 30));
+        let point = center(-7, 4);
+        assert_eq!(ObstacleMap::cell(point.x, point.y), IVec2::new(-7, 4));
+    }
+
+    #[test]
+    fn a_point_only_sees_its_cell() {
+        let map = map_with(&[(3, 3)]);
+        let blocked = center(3, 3);
+        let next = center(4, 3);
+        assert!(map.is_obstacle(blocked.x, blocked.y, 0.0));
+        assert!(!map.is_obstacle(next.x, next.y, 0.0));
+    }
+
+    #[test]
+    fn a_footprint_overlaps_neighbouring_cells() {
+        let map = map_with(&[(3, 3)]);
+        let next = center(4, 3);
+        assert!(!map.is_obstacle(next.x, next.y, ANT));
+        assert!(map.is_obstacle(next.x, next.y, ANTEATER));
+        // diagonal cells are further than their side cells
+        let diagonal = center(4, 4);
+        let corner_distance = (0.5 / DEF) * 2.0_f32.sqrt();
+        assert!(!map.is_obstacle(diagonal.x, diagonal.y, corner_distance - 0.001));
+        assert!(map.is_obstacle(diagonal.x, diagonal.y, corner_distance + 0.001));
+    }
+
+    #[test]
+    fn ants_fit_through_gaps_anteaters_do_not() {
+        // a wall along z with a one cell gap at z = 5
+        let wall = (0..10)
+            .filter(|j| *j != 5)
+            .map(|j| (5, j))
+            .collect::<Vec<_>>();
+        let map = map_with(&wall);
+        let gap = center(5, 5);
+        assert!(!map.is_obstacle(gap.x, gap.y, ANT));
+        assert!(map.is_obstacle(gap.x, gap.y, ANTEATER));
+        let (before, after) = (center(2, 5), center(8, 5));
+        assert!(map.is_path_clear(before, after, ANT));
+        assert!(!map.is_path_clear(before, after, ANTEATER));
+    }
+
+    #[test]
+    fn paths_are_blocked_by_thin_walls() {
+        let map = map_with(&[(5, 4), (5, 5), (5, 6)]);
+        assert!(!map.is_path_clear(center(2, 5), center(8, 5), 0.0));
+        assert!(map.is_path_clear(center(2, 1), center(8, 1), ANT));
+        assert!(map.is_path_clear(center(2, 5), center(2, 5), ANT));
+    }
+
+    #[test]
+    fn nearest_free_cell_is_the_closest_one_that_fits() {
+        let map = map_with(&[(4, 5), (5, 5), (6, 5), (5, 4), (5, 6)]);
+        let inside = center(5, 5);
+        // the side cells are blocked, the diagonal ones come next
+        let free = map.nearest_free(inside.x, inside.y, 0.0, 0.2).unwrap();
+        assert!((free.distance(inside) - 2.0_f32.sqrt() / DEF).abs() < 0.001);
+
+        let free = map.nearest_free(inside.x, inside.y, ANT, 0.2).unwrap();
+        assert!(!map.is_obstacle(free.x, free.y, ANT));
+
+        let clear = center(0, 0);
+        assert_eq!(map.nearest_free(clear.x, clear.y, ANT, 0.2), Some(clear));
+        assert_eq!(map.nearest_free(inside.x, inside.y, ANT, 0.01), None);
+    }
+
+    #[test]
+    fn crossings_open_water_and_placed_obstacles_can_be_removed() {
+        let mut map = ObstacleMap::default();
+        set_cell(&mut map, IVec2::new(5, 5), true, true);
+        let water = center(5, 5);
+        assert!(map.is_obstacle(water.x, water.y, 0.0));
+        map.place_crossing(water.x, water.y, 0.01);
+        assert!(!map.is_obstacle(water.x, water.y, 0.0));
+
+        let spot = center(10, 10);
+        map.place_obstacle(spot.x, spot.y, 0.1);
+        assert!(map.is_obstacle(spot.x, spot.y, 0.0));
+        // an overlapping obstacle keeps the cells they share
+        let next = center(12, 10);
+        map.place_obstacle(next.x, next.y, 0.1);
+        map.remove_obstacle(spot.x, spot.y, 0.1);
+        assert!(map.is_obstacle(center(11, 10).x, center(11, 10).y, 0.0));
+        assert!(!map.is_obstacle(center(9, 10).x, center(9, 10).y, 0.0));
+        map.remove_obstacle(next.x, next.y, 0.1);
+        assert!(!map.is_obstacle(spot.x, spot.y, ANTEATER));
+
+        map.clear_placed();
+        assert!(map.is_obstacle(water.x, water.y, 0.0));
+    }
+
+    #[test]
+    fn creatures_lean_towards_easier_ground() {
+        let mut map = map_with(&[]);
+        set_cell(&mut map, IVec2::new(10, 10), false, false);
+        let start = center(10, 10);
+        let heading = map.easiest_heading(start.x, start.y, Vec3::X, 2.0 / DEF, ANT);
+        assert_eq!(heading, Vec3::X);
+
+        // mud straight ahead
+        for i in 11..=13 {
+            let (lot, index) = ObstacleMap::locate(IVec2::new(i, 10));
+            map.chunks.get_mut(&lot).unwrap().ground[index].movement_cost = 4.0;
+        }
+        let heading = map.easiest_heading(start.x, start.y, Vec3::X, 2.0 / DEF, ANT);
+        assert!(heading.x > 0.5 && heading.z.abs() > 0.1);
+
+        // but not through an obstacle
+        set_cell(&mut map, IVec2::new(12, 9), true, false);
+        set_cell(&mut map, IVec2::new(12, 11), true, false);
+        set_cell(&mut map, IVec2::new(11, 12), true, false);
+        set_cell(&mut map, IVec2::new(11, 8), true, false);
+        let heading = map.easiest_heading(start.x, start.y, Vec3::X, 2.0 / DEF, ANT);
+        assert_eq!(heading, Vec3::X);
+    }
+}
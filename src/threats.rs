@@ -0,0 +1,311 @@
+use std::{collections::VecDeque, f32::consts::PI};
+
+use bevy::{
+    prelude::*,
+    render::camera::{Camera, PerspectiveProjection},
+};
+use rand::{prelude::SliceRandom, Rng};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ant_hill::{AntHill, Colony, MainHill},
+    ants::Creature,
+    food::WorldEvents,
+    game_state::{Difficulty, GameState},
+    predators::PredatorRoster,
+    terrain_spawner::ObstacleMap,
+    ui::GraphData,
+    BORDER,
+};
+
+const THREATS_FILE: &str = "threats.conf";
+/// Seconds between two threats of the same wave
+const SPAWN_GAP: f32 = 1.0;
+/// Seconds a wave stays announced
+const ANNOUNCE_TIME: f32 = 8.0;
+/// Spawn points tried along the edges of the map
+const EDGE_POINTS: usize = 32;
+
+pub struct ThreatsPlugin;
+
+impl Plugin for ThreatsPlugin {
+    fn build(&self, app: &mut App) {
+        let config = match config::read_from::<ThreatConfig>(THREATS_FILE) {
+            Ok(config) => config,
+            Err(error) => {
+                warn!("could not read {}, using defaults: {}", THREATS_FILE, error);
+                ThreatConfig::default()
+            }
+        };
+        app.insert_resource(config)
+            .init_resource::<ThreatDirector>()
+            .add_system_set(SystemSet::on_enter(GameState::Playing).with_system(reset_director))
+            .add_system_set(
+                SystemSet::on_update(GameState::Playing)
+                    .with_system(schedule_waves)
+                    .with_system(spawn_threats),
+            );
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub enum Threat {
+    AntEater,
+    /// Id of a species of the predator roster
+    Predator(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct WaveUnit {
+    pub threat: Threat,
+    /// First wave it takes part in, starting at 1
+    pub from_wave: u32,
+    pub count: f32,
+    /// Added to `count` for each wave after `from_wave`
+    pub per_wave: f32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ThreatLevel {
+    pub name: String,
+    /// Used once the game difficulty reaches it
+    pub difficulty: f32,
+    /// Seconds between two waves
+    pub interval: f32,
+    /// Multiplies the count of every unit
+    pub multiplier: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ThreatConfig {
+    /// Colony strength that brings the first wave
+    pub first_wave_strength: f32,
+    /// Seconds into the game when the first wave comes anyway
+    pub first_wave_deadline: f32,
+    pub units: Vec<WaveUnit>,
+    pub levels: Vec<ThreatLevel>,
+}
+
+impl ThreatConfig {
+    fn level(&self, difficulty: f32) -> Option<&ThreatLevel> {
+        self.levels
+            .iter()
+            .filter(|level| level.difficulty <= difficulty)
+            .max_by(|a, b| a.difficulty.partial_cmp(&b.difficulty).unwrap())
+    }
+
+    fn composition(&self, wave: u32, multiplier: f32) -> Vec<(Threat, u32)> {
+        self.units
+            .iter()
+            .filter(|unit| unit.from_wave <= wave)
+            .map(|unit| {
+                let count = unit.count + unit.per_wave * (wave - unit.from_wave) as f32;
+                (unit.threat.clone(), (count * multiplier) as u32)
+            })
+            .filter(|(_, count)| *count > 0)
+            .collect()
+    }
+}
+
+impl Default for ThreatConfig {
+    fn default() -> Self {
+        let unit = |threat: Threat, from_wave: u32, count: f32, per_wave: f32| WaveUnit {
+            threat,
+            from_wave,
+            count,
+            per_wave,
+        };
+        let level = |name: &str, difficulty: f32, interval: f32, multiplier: f32| ThreatLevel {
+            name: name.to_string(),
+            difficulty,
+            interval,
+            multiplier,
+        };
+        ThreatConfig {
+            first_wave_strength: 1500.0,
+            first_wave_deadline: 540.0,
+            units: vec![
+                unit(Threat::AntEater, 1, 4.0, 2.0),
+                unit(Threat::Predator("spider".to_string()), 2, 2.0, 1.0),
+                unit(Threat::Predator("bird".to_string()), 3, 1.0, 1.0),
+                unit(Threat::Predator("rain".to_string()), 5, 1.0, 0.5),
+            ],
+            levels: vec![
+                level("Normal", 0.0, 30.0, 1.0),
+                level("Hard", 2.0, 25.0, 1.5),
+                level("Brutal", 3.0, 20.0, 2.0),
+            ],
+        }
+    }
+}
+
+pub struct Announcement {
+    pub text: String,
+    pub until: f32,
+}
+
+/// Scores the player colony and sends waves of threats once it grows too strong
+#[derive(Default)]
+pub struct ThreatDirector {
+    pub wave: u32,
+    /// Seconds into the game of the next wave, once the waves started
+    next_wave: Option<f32>,
+    pending: VecDeque<Threat>,
+    next_spawn: f32,
+    pub announcement: Option<Announcement>,
+    pub elapsed: f32,
+}
+
+impl ThreatDirector {
+    pub fn current_announcement(&self) -> Option<&str> {
+        self.announcement
+            .as_ref()
+            .filter(|announcement| announcement.until > self.elapsed)
+            .map(|announcement| announcement.text.as_str())
+    }
+}
+
+/// How threatening the colony looks: living ants, ants raised, hills and the queen's reserve
+fn colony_strength(ants: u32, total_ants: u32, hills: u32, queen_food: u32) -> f32 {
+    ants as f32 + total_ants as f32 / 4.0 + (hills.max(1) - 1) as f32 * 150.0 + queen_food as f32
+}
+
+fn reset_director(mut director: ResMut<ThreatDirector>) {
+    *director = ThreatDirector::default();
+}
+
+#[allow(clippy::too_many_arguments)]
+fn schedule_waves(
+    mut director: ResMut<ThreatDirector>,
+    config: Res<ThreatConfig>,
+    roster: Res<PredatorRoster>,
+    data: Res<GraphData>,
+    difficulty: Res<Difficulty>,
+    time: Res<Time>,
+    creatures: Query<&Creature>,
+    hills: Query<&Colony, With<AntHill>>,
+    main_hill: Query<&AntHill, With<MainHill>>,
+) {
+    director.elapsed = (time.time_since_startup() - data.start_time).as_secs_f32();
+    let level = if let Some(level) = config.level(difficulty.0) {
+        level
+    } else {
+        return;
+    };
+    match director.next_wave {
+        None => {
+            let ants = creatures
+                .iter()
+                .filter(|creature| creature.colony == Colony::PLAYER)
+                .count() as u32;
+            let hills = hills
+                .iter()
+                .filter(|colony| **colony == Colony::PLAYER)
+                .count() as u32;
+            let queen_food = main_hill.get_single().map_or(0, |hill| hill.queen_food);
+            let strength = colony_strength(ants, data.total_ants, hills, queen_food);
+            if strength >= config.first_wave_strength
+                || director.elapsed >= config.first_wave_deadline
+            {
+                debug!("colony strength {} brings the first wave", strength);
+                director.next_wave = Some(director.elapsed);
+            }
+        }
+        Some(next_wave) if director.elapsed >= next_wave => {
+            director.wave += 1;
+            let composition = config.composition(director.wave, level.multiplier);
+            let mut text = format!("Wave {} ({}):", director.wave, level.name);
+            for (threat, count) in composition.iter() {
+                text.push_str(&format!(" {} {},", count, threat_name(threat, &*roster)));
+                director
+                    .pending
+                    .extend(std::iter::repeat(threat.clone()).take(*count as usize));
+            }
+            text.pop();
+            director.announcement = Some(Announcement {
+                text,
+                until: director.elapsed + ANNOUNCE_TIME,
+            });
+            director.next_wave = Some(director.elapsed + level.interval);
+        }
+        Some(_) => (),
+    }
+}
+
+fn threat_name<'a>(threat: &'a Threat, roster: &'a PredatorRoster) -> &'a str {
+    match threat {
+        Threat::AntEater => "Anteater",
+        Threat::Predator(id) => roster
+            .species
+            .iter()
+            .find(|species| &species.id == id)
+            .map_or(id, |species| &species.name),
+    }
+}
+
+fn spawn_threats(
+    mut director: ResMut<ThreatDirector>,
+    roster: Res<PredatorRoster>,
+    obstacle_map: Res<ObstacleMap>,
+    camera: Query<&GlobalTransform, (With<Camera>, With<PerspectiveProjection>)>,
+    mut events: EventWriter<WorldEvents>,
+) {
+    if director.pending.is_empty() || director.elapsed < director.next_spawn {
+        return;
+    }
+    director.next_spawn = director.elapsed + SPAWN_GAP;
+    // the threat waits for the next try when no edge is free
+    let position =
+        if let Some(position) = edge_spawn_point(&*obstacle_map, camera.get_single().ok()) {
+            position
+        } else {
+            debug!("no free edge to spawn {:?}", director.pending.front());
+            return;
+        };
+    let threat = director.pending.pop_front().unwrap();
+    match threat_event(&threat, position, &*roster) {
+        Some(event) => events.send(event),
+        None => warn!("unknown predator {:?} in {}", threat, THREATS_FILE),
+    }
+}
+
+/// The event spawning a threat, if it is known
+pub fn threat_event(
+    threat: &Threat,
+    position: Vec3,
+    roster: &PredatorRoster,
+) -> Option<WorldEvents> {
+    match threat {
+        Threat::AntEater => Some(WorldEvents::SpawnAntEater(position)),
+        Threat::Predator(id) => roster
+            .species
+            .iter()
+            .position(|species| &species.id == id)
+            .map(|species| WorldEvents::SpawnPredator(species, position)),
+    }
+}
+
+/// A free point along the edges of the map, among the half furthest from the camera
+fn edge_spawn_point(obstacle_map: &ObstacleMap, camera: Option<&GlobalTransform>) -> Option<Vec3> {
+    let looking_at = camera
+        .map(|camera| Vec3::new(camera.translation.x, 0.0, camera.translation.z))
+        .unwrap_or(Vec3::ZERO);
+    let offset = rand::thread_rng().gen_range(0.0..(2.0 * PI));
+    let mut points = (0..EDGE_POINTS)
+        .map(|i| {
+            let direction =
+                Quat::from_rotation_y(offset + i as f32 * 2.0 * PI / EDGE_POINTS as f32)
+                    .mul_vec3(Vec3::X);
+            // push the direction onto the square border
+            direction / direction.x.abs().max(direction.z.abs()) * BORDER * 0.95
+        })
+        .filter(|point| !obstacle_map.is_obstacle(point.x, point.z, 0.0))
+        .collect::<Vec<_>>();
+    points.sort_by(|a, b| {
+        b.distance_squared(looking_at)
+            .partial_cmp(&a.distance_squared(looking_at))
+            .unwrap()
+    });
+    points.truncate((points.len() + 1) / 2);
+    points.choose(&mut rand::thread_rng()).copied()
+}
@@ -4,8 +4,8 @@ use bevy::{
     core::{Time, Timer},
     math::Vec3,
     prelude::{
-        ConfigurableSystem, EventWriter, Local, Or, Plugin, Query, Res, ResMut, State, SystemSet,
-        With,
+        ConfigurableSystem, Entity, EventWriter, Local, Or, Plugin, Query, Res, ResMut, State,
+        SystemSet, With, Without,
     },
 };
 use bevy_egui::{
@@ -22,10 +22,15 @@ const HISTORY_SIZE: usize = 240;
 
 use crate::{
     ant_eaters::AntEater,
-    ant_hill::{AntHill, EvolveTimer, HillEvents},
+    ant_hill::{AntHill, BroodStage, Colony, EvolveTimer, HillEvent, MainHill},
     ants::Creature,
-    food::{FoodPellet, WorldEvents},
+    build_tools::{BuildTool, SelectedTool},
+    food::{FoodHeap, FoodKinds, WorldEvents},
     game_state::GameState,
+    plants::Plant,
+    scenario::ActiveScenario,
+    threats::ThreatDirector,
+    upgrades::{Upgrade, UpgradeLevels, UpgradeTree},
     BORDER,
 };
 
@@ -53,13 +58,14 @@ pub struct GraphData {
     genome_speed: f32,
     genome_expectancy: f64,
     genome_antennas: f32,
-    wave: f32,
+    eggs: usize,
+    larvae: usize,
+    delivered: Vec<(String, u32)>,
     pub max_ants: u32,
     pub total_ants: u32,
     pub start_time: Duration,
     pub end_time: Duration,
     can_summon_food: bool,
-    appocalypse: bool,
 }
 impl GraphData {
     pub fn from_anthill(anthill: AntHill, time: &Time) -> Self {
@@ -69,7 +75,6 @@ impl GraphData {
         let genome_speed = anthill.gene.max_speed;
         let genome_expectancy = anthill.gene.life_expectancy;
         let genome_antennas = anthill.gene.antennas;
-        let wave = anthill.spawn_per_wave;
         let food = anthill.food;
         Self {
             nb_ants,
@@ -78,46 +83,14 @@ impl GraphData {
             genome_speed,
             genome_expectancy,
             genome_antennas,
-            wave,
+            eggs: 0,
+            larvae: 0,
+            delivered: vec![],
             max_ants: 0,
             total_ants: 0,
             start_time: time.time_since_startup(),
             end_time: time.time_since_startup(),
             can_summon_food: false,
-            appocalypse: false,
-        }
-    }
-}
-
-pub struct Bonuses {
-    spawn_cost: u32,
-    spawn: u32,
-    improve_wave_cost: u32,
-    improve_wave: f32,
-    improve_speed_cost: u32,
-    improve_speed: f32,
-    improve_life_cost: u32,
-    improve_life: f64,
-    improve_antennas_cost: u32,
-    improve_antennas: f32,
-    improve_mutation_cost: u32,
-    improve_mutation: f32,
-}
-impl Default for Bonuses {
-    fn default() -> Self {
-        Bonuses {
-            spawn_cost: 10,
-            spawn: 15,
-            improve_wave_cost: 10,
-            improve_wave: 1.75,
-            improve_speed_cost: 5,
-            improve_speed: 0.005,
-            improve_life_cost: 5,
-            improve_life: 5.0,
-            improve_antennas_cost: 5,
-            improve_antennas: 1.0,
-            improve_mutation_cost: 15,
-            improve_mutation: 0.6,
         }
     }
 }
@@ -125,22 +98,30 @@ impl Default for Bonuses {
 #[allow(clippy::too_many_arguments)]
 fn update_graph_data(
     creatures: Query<&Creature>,
-    todo: Query<(), Or<(With<FoodPellet>, With<AntEater>)>>,
+    todo: Query<(), (Or<(With<FoodHeap>, With<AntEater>)>, Without<Plant>)>,
     mut data: ResMut<GraphData>,
     mut timer: Local<Timer>,
     time: Res<Time>,
-    anthill: Res<AntHill>,
+    main_hill: Query<&AntHill, With<MainHill>>,
     mut state: ResMut<State<GameState>>,
-    mut events: EventWriter<WorldEvents>,
-    mut corner: Local<u8>,
+    food_kinds: Res<FoodKinds>,
+    scenario: Res<ActiveScenario>,
 ) {
+    let anthill = if let Ok(anthill) = main_hill.get_single() {
+        anthill
+    } else {
+        return;
+    };
     if timer.tick(time.delta()).just_finished() {
-        let creature_count = creatures.iter().len() as u32;
+        let creature_count = creatures
+            .iter()
+            .filter(|creature| creature.colony == Colony::PLAYER)
+            .count() as u32;
         if data.max_ants > 0 && creature_count == 0 {
             data.end_time = time.time_since_startup();
             state.set(GameState::Lost).unwrap();
-        } else if anthill.queen_food >= 200
-            || (creature_count > 100 && todo.iter().next().is_none())
+        } else if !scenario.has_objectives()
+            && (anthill.queen_food >= 200 || (creature_count > 100 && todo.iter().next().is_none()))
         {
             data.end_time = time.time_since_startup();
             state.set(GameState::Won).unwrap();
@@ -155,54 +136,43 @@ fn update_graph_data(
         if !data.can_summon_food && rand::thread_rng().gen_bool(0.005) {
             data.can_summon_food = true;
         }
-        if !data.appocalypse
-            && (time.time_since_startup() - data.start_time > Duration::from_secs(540)
-                || creature_count > 1500
-                || data.total_ants > 6000)
-        {
-            data.appocalypse = true;
-        }
-        if data.appocalypse {
-            *corner += 1;
-            events.send(WorldEvents::SpawnAntEater(match *corner {
-                1 => Vec3::new(BORDER, 0.0, BORDER),
-                2 => Vec3::new(BORDER, 0.0, BORDER / 2.0),
-                3 => Vec3::new(BORDER, 0.0, 0.0),
-                4 => Vec3::new(BORDER, 0.0, -BORDER / 2.0),
-                5 => Vec3::new(BORDER, 0.0, -BORDER),
-                6 => Vec3::new(BORDER / 2.0, 0.0, -BORDER),
-                7 => Vec3::new(0.0, 0.0, -BORDER),
-                8 => Vec3::new(-BORDER / 2.0, 0.0, -BORDER),
-                9 => Vec3::new(-BORDER, 0.0, -BORDER),
-                10 => Vec3::new(-BORDER, 0.0, -BORDER / 2.0),
-                11 => Vec3::new(-BORDER, 0.0, 0.0),
-                12 => Vec3::new(-BORDER, 0.0, BORDER / 2.0),
-                13 => Vec3::new(-BORDER, 0.0, BORDER),
-                14 => Vec3::new(-BORDER / 2.0, 0.0, BORDER),
-                15 => Vec3::new(0.0, 0.0, BORDER),
-                _ => {
-                    *corner = 0;
-                    Vec3::new(BORDER / 2.0, 0.0, BORDER)
-                }
-            }));
-        }
     }
     data.queen_food = anthill.queen_food;
     data.genome_speed = anthill.gene.max_speed;
     data.genome_expectancy = anthill.gene.life_expectancy;
     data.genome_antennas = anthill.gene.antennas;
     data.food = anthill.food;
-    data.wave = anthill.spawn_per_wave;
+    data.eggs = anthill.brood_count(BroodStage::Egg);
+    data.larvae = anthill.brood_count(BroodStage::Larva);
+    data.delivered = food_kinds
+        .kinds
+        .iter()
+        .filter_map(|kind| {
+            anthill
+                .delivered
+                .get(&kind.id)
+                .map(|count| (kind.name.clone(), *count))
+        })
+        .collect();
 }
 
+#[allow(clippy::too_many_arguments)]
 fn overall_ui(
     egui_context: ResMut<EguiContext>,
     mut data: ResMut<GraphData>,
-    mut bonuses: ResMut<Bonuses>,
-    mut events: EventWriter<HillEvents>,
+    tree: Res<UpgradeTree>,
+    mut levels: ResMut<UpgradeLevels>,
+    mut main_hill: Query<(Entity, &mut AntHill), With<MainHill>>,
+    mut events: EventWriter<HillEvent>,
     mut world_events: EventWriter<WorldEvents>,
     evolve_timer: Res<EvolveTimer>,
+    mut selected_tool: ResMut<SelectedTool>,
+    director: Res<ThreatDirector>,
 ) {
+    // read from the hill rather than the stats, which are updated once a second
+    let queen_food = main_hill
+        .get_single()
+        .map_or(0, |(_, anthill)| anthill.queen_food);
     egui::SidePanel::left("left-panel")
         .resizable(false)
         .show(egui_context.ctx(), |ui| {
@@ -216,6 +186,20 @@ fn overall_ui(
                         ui.label("Ants");
                         ui.label(format!("{}", data.nb_ants.back().unwrap_or(&0)));
                         ui.end_row();
+                        ui.label("Larvae");
+                        ui.label(format!("{}", data.larvae));
+                        ui.end_row();
+                        ui.label("Eggs");
+                        ui.label(format!("{}", data.eggs));
+                        ui.end_row();
+                        ui.label("Food");
+                        ui.label(format!("{}", data.food));
+                        ui.end_row();
+                        for (kind, count) in data.delivered.iter() {
+                            ui.label(format!("  {}", kind));
+                            ui.label(format!("{}", count));
+                            ui.end_row();
+                        }
                     });
                 Plot::new("ant count")
                     .height(150.0)
@@ -230,10 +214,6 @@ fn overall_ui(
                                 .map(|(i, c)| Value::new(i as f64, (*c) as f32)),
                         )))
                     });
-                ui.add(
-                    ProgressBar::new(data.food as f32 / 10.0)
-                        .text(format!("Spawn {} new ants", data.wave as u32)),
-                );
             });
             ui.label("");
             ui.group(|ui| {
@@ -267,115 +247,106 @@ fn overall_ui(
                     .min_row_height(25.0)
                     .show(ui, |ui| {
                         ui.label("Available");
-                        ui.label(format!("{}", data.queen_food));
-                        ui.end_row();
-                        ui.scope(|ui| {
-                            if data.queen_food < bonuses.spawn_cost {
-                                ui.set_enabled(false);
-                            }
-                            if ui.button(format!("Spawn {} Ants", bonuses.spawn)).clicked() {
-                                events.send(HillEvents::SpawnAnts {
-                                    count: bonuses.spawn,
-                                });
-                                events.send(HillEvents::RemoveQueenFood(bonuses.spawn_cost));
-                                bonuses.spawn += 1;
-                                bonuses.spawn_cost += 1;
-                            }
-                        });
-                        ui.label(format!("{}", bonuses.spawn_cost));
-                        ui.end_row();
-                        ui.scope(|ui| {
-                            if data.queen_food < bonuses.improve_wave_cost {
-                                ui.set_enabled(false);
-                            }
-                            if ui.button("Improve Ant Spawning").clicked() {
-                                events.send(HillEvents::ImproveWave(bonuses.improve_wave));
-                                events.send(HillEvents::RemoveQueenFood(bonuses.improve_wave_cost));
-                                bonuses.spawn += 2;
-                                bonuses.improve_wave_cost += 15;
-                            }
-                        });
-                        ui.label(format!("{}", bonuses.improve_wave_cost));
-                        ui.end_row();
-                        ui.scope(|ui| {
-                            if data.queen_food < bonuses.improve_speed_cost {
-                                ui.set_enabled(false);
-                            }
-                            if ui.button("Improve Speed").clicked() {
-                                events.send(HillEvents::ImproveMaxSpeed(bonuses.improve_speed));
-                                events
-                                    .send(HillEvents::RemoveQueenFood(bonuses.improve_speed_cost));
-                                bonuses.improve_speed_cost += 5;
-                                bonuses.improve_speed += 0.002;
-                            }
-                        });
-
-                        ui.label(format!("{}", bonuses.improve_speed_cost));
-                        ui.end_row();
-                        ui.scope(|ui| {
-                            if data.queen_food < bonuses.improve_life_cost {
-                                ui.set_enabled(false);
-                            }
-                            if ui.button("Improve Life Expectancy").clicked() {
-                                events
-                                    .send(HillEvents::ImproveLifeExpectancy(bonuses.improve_life));
-                                events.send(HillEvents::RemoveQueenFood(bonuses.improve_life_cost));
-                                bonuses.improve_life_cost += 5;
-                                bonuses.improve_life += 2.0;
-                            }
-                        });
-                        ui.label(format!("{}", bonuses.improve_life_cost));
-                        ui.end_row();
-                        ui.scope(|ui| {
-                            if data.queen_food < bonuses.improve_antennas_cost {
-                                ui.set_enabled(false);
-                            }
-                            if ui.button("Improve Food Sensibility").clicked() {
-                                events.send(HillEvents::ImproveAntennas(bonuses.improve_antennas));
-                                events.send(HillEvents::RemoveQueenFood(
-                                    bonuses.improve_antennas_cost,
-                                ));
-                                bonuses.improve_antennas_cost += 5;
-                                bonuses.improve_antennas += 1.0;
-                            }
-                        });
-                        ui.label(format!("{}", bonuses.improve_antennas_cost));
-                        ui.end_row();
-                        ui.scope(|ui| {
-                            if data.queen_food < bonuses.improve_mutation_cost {
-                                ui.set_enabled(false);
-                            }
-                            if ui.button("Improve Mutations").clicked() {
-                                events.send(HillEvents::ImproveMutation(bonuses.improve_mutation));
-                                events.send(HillEvents::RemoveQueenFood(
-                                    bonuses.improve_mutation_cost,
-                                ));
-                                bonuses.improve_mutation_cost += 15;
-                                bonuses.improve_mutation += 0.15;
-                            }
-                        });
-                        ui.label(format!("{}", bonuses.improve_mutation_cost));
+                        ui.label(format!("{}", queen_food));
                         ui.end_row();
-                        if !data.can_summon_food {
-                            ui.label("");
-                        } else if ui.button("Create Food").clicked() {
-                            world_events.send(WorldEvents::SpawnFood(true));
-                            world_events
-                                .send(WorldEvents::SpawnAntEater(Vec3::new(BORDER, 0.0, BORDER)));
-                            world_events
-                                .send(WorldEvents::SpawnAntEater(Vec3::new(BORDER, 0.0, -BORDER)));
-                            world_events
-                                .send(WorldEvents::SpawnAntEater(Vec3::new(-BORDER, 0.0, BORDER)));
-                            world_events
-                                .send(WorldEvents::SpawnAntEater(Vec3::new(-BORDER, 0.0, -BORDER)));
-                            data.can_summon_food = false
+                    });
+                if let Ok((hill, mut anthill)) = main_hill.get_single_mut() {
+                    for upgrade in tree.roots() {
+                        upgrade_node(
+                            ui,
+                            &*tree,
+                            upgrade,
+                            hill,
+                            &mut *levels,
+                            &mut anthill.queen_food,
+                            &mut events,
+                        );
+                    }
+                }
+                if data.can_summon_food && ui.button("Create Food").clicked() {
+                    world_events.send(WorldEvents::SpawnFood(true));
+                    world_events.send(WorldEvents::SpawnAntEater(Vec3::new(BORDER, 0.0, BORDER)));
+                    world_events.send(WorldEvents::SpawnAntEater(Vec3::new(BORDER, 0.0, -BORDER)));
+                    world_events.send(WorldEvents::SpawnAntEater(Vec3::new(-BORDER, 0.0, BORDER)));
+                    world_events.send(WorldEvents::SpawnAntEater(Vec3::new(-BORDER, 0.0, -BORDER)));
+                    data.can_summon_food = false
+                }
+            });
+            ui.label("");
+            ui.group(|ui| {
+                ui.label("Build");
+                ui.separator();
+                for tool in BuildTool::ALL {
+                    ui.scope(|ui| {
+                        if queen_food < tool.cost() {
+                            ui.set_enabled(false);
+                        }
+                        let selected = selected_tool.0 == Some(tool);
+                        if ui
+                            .selectable_label(
+                                selected,
+                                format!("{} ({})", tool.name(), tool.cost()),
+                            )
+                            .on_hover_text(tool.description())
+                            .clicked()
+                        {
+                            selected_tool.0 = if selected { None } else { Some(tool) };
                         }
-                        ui.end_row();
                     });
+                }
+                if selected_tool.0.is_some() {
+                    ui.label("Click on the ground, right click to cancel");
+                }
             });
-            if data.appocalypse {
+            if let Some(announcement) = director.current_announcement() {
                 ui.label("");
-                ui.label("You colony has been found!");
+                ui.label(announcement);
+            }
+        });
+}
+
+fn upgrade_node(
+    ui: &mut egui::Ui,
+    tree: &UpgradeTree,
+    upgrade: &Upgrade,
+    hill: Entity,
+    levels: &mut UpgradeLevels,
+    queen_food: &mut u32,
+    events: &mut EventWriter<HillEvent>,
+) {
+    let level = levels.level(&upgrade.id);
+    let cost = levels.cost(upgrade);
+    let maxed = levels.is_maxed(upgrade);
+    ui.horizontal(|ui| {
+        ui.scope(|ui| {
+            if *queen_food < cost || !levels.is_unlocked(upgrade) || maxed {
+                ui.set_enabled(false);
+            }
+            let name = if level > 0 {
+                format!("{} ({})", upgrade.name, level)
+            } else {
+                upgrade.name.clone()
+            };
+            if ui
+                .button(name)
+                .on_hover_text(&upgrade.description)
+                .on_disabled_hover_text(&upgrade.description)
+                .clicked()
+            {
+                for event in levels.buy(upgrade, queen_food).unwrap_or_default() {
+                    events.send(HillEvent { hill, event });
+                }
             }
         });
+        if maxed {
+            ui.label("max");
+        } else {
+            ui.label(format!("{}", cost));
+        }
+    });
+    ui.indent(&upgrade.id, |ui| {
+        for child in tree.children_of(&upgrade.id) {
+            upgrade_node(ui, tree, child, hill, levels, queen_food, events);
+        }
+    });
 }
@@ -0,0 +1,222 @@
+use bevy::{prelude::*, utils::HashMap};
+use serde::{Deserialize, Serialize};
+
+use crate::ant_hill::HillEvents;
+
+const UPGRADES_FILE: &str = "upgrades.conf";
+
+pub struct UpgradesPlugin;
+
+impl Plugin for UpgradesPlugin {
+    fn build(&self, app: &mut App) {
+        let tree = match config::read_from::<UpgradeTree>(UPGRADES_FILE) {
+            Ok(tree) => tree,
+            Err(error) => {
+                warn!(
+                    "could not read {}, using defaults: {}",
+                    UPGRADES_FILE, error
+                );
+                UpgradeTree::default()
+            }
+        };
+        app.insert_resource(tree).init_resource::<UpgradeLevels>();
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct CostCurve {
+    pub base: u32,
+    pub per_level: u32,
+    pub growth: f32,
+}
+
+impl CostCurve {
+    pub fn at_level(&self, level: u32) -> u32 {
+        ((self.base + self.per_level * level) as f32 * self.growth.powi(level as i32)) as u32
+    }
+}
+
+/// Raises the effects of an upgrade with each level bought of another one
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Boost {
+    pub upgrade: String,
+    /// Added to the matching entry of `effects` for each level of `upgrade`
+    pub effects_per_level: Vec<HillEvents>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Upgrade {
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    pub cost: CostCurve,
+    pub effects: Vec<HillEvents>,
+    /// Added to the matching entry of `effects` for each level already bought
+    pub effects_per_level: Vec<HillEvents>,
+    pub requires: Vec<String>,
+    pub max_level: Option<u32>,
+    #[serde(default)]
+    pub boosted_by: Vec<Boost>,
+}
+
+impl Upgrade {
+    pub fn effects_at_level(&self, level: u32) -> impl Iterator<Item = HillEvents> + '_ {
+        self.effects.iter().enumerate().map(move |(i, effect)| {
+            match self.effects_per_level.get(i) {
+                Some(increase) => effect.increased_by(increase, level),
+                None => effect.clone(),
+            }
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UpgradeTree {
+    pub upgrades: Vec<Upgrade>,
+}
+
+impl UpgradeTree {
+    pub fn roots(&self) -> impl Iterator<Item = &Upgrade> {
+        self.upgrades
+            .iter()
+            .filter(|upgrade| upgrade.requires.is_empty())
+    }
+
+    /// Upgrades displayed under `parent`, which is their first prerequisite
+    pub fn children_of<'a>(&'a self, parent: &'a str) -> impl Iterator<Item = &'a Upgrade> {
+        self.upgrades
+            .iter()
+            .filter(move |upgrade| upgrade.requires.first().map(String::as_str) == Some(parent))
+    }
+}
+
+impl Default for UpgradeTree {
+    fn default() -> Self {
+        let upgrade = |id: &str,
+                       name: &str,
+                       description: &str,
+                       cost: (u32, u32),
+                       effect: HillEvents,
+                       per_level: Option<HillEvents>| Upgrade {
+            id: id.to_string(),
+            name: name.to_string(),
+            description: description.to_string(),
+            cost: CostCurve {
+                base: cost.0,
+                per_level: cost.1,
+                growth: 1.0,
+            },
+            effects: vec![effect],
+            effects_per_level: per_level.into_iter().collect(),
+            requires: vec![],
+            max_level: None,
+            boosted_by: vec![],
+        };
+        UpgradeTree {
+            upgrades: vec![
+                Upgrade {
+                    boosted_by: vec![Boost {
+                        upgrade: "improve_spawning".to_string(),
+                        effects_per_level: vec![HillEvents::SpawnAnts { count: 2 }],
+                    }],
+                    ..upgrade(
+                        "spawn_ants",
+                        "Spawn Ants",
+                        "Hatch a batch of ants right now. Each batch is bigger than the last.",
+                        (10, 1),
+                        HillEvents::SpawnAnts { count: 15 },
+                        Some(HillEvents::SpawnAnts { count: 1 }),
+                    )
+                },
+                upgrade(
+                    "improve_spawning",
+                    "Improve Ant Spawning",
+                    "The queen lays eggs faster, and more ants hatch in each batch.",
+                    (10, 15),
+                    HillEvents::ImproveWave(1.75),
+                    None,
+                ),
+                upgrade(
+                    "improve_speed",
+                    "Improve Speed",
+                    "Ants move faster, but tire sooner.",
+                    (5, 5),
+                    HillEvents::ImproveMaxSpeed(0.005),
+                    Some(HillEvents::ImproveMaxSpeed(0.002)),
+                ),
+                upgrade(
+                    "improve_life",
+                    "Improve Life Expectancy",
+                    "Ants live longer.",
+                    (5, 5),
+                    HillEvents::ImproveLifeExpectancy(5.0),
+                    Some(HillEvents::ImproveLifeExpectancy(2.0)),
+                ),
+                upgrade(
+                    "improve_antennas",
+                    "Improve Food Sensibility",
+                    "Ants notice food from further away.",
+                    (5, 5),
+                    HillEvents::ImproveAntennas(1.0),
+                    Some(HillEvents::ImproveAntennas(1.0)),
+                ),
+                upgrade(
+                    "improve_mutations",
+                    "Improve Mutations",
+                    "Each mutation pushes the genome further.",
+                    (15, 15),
+                    HillEvents::ImproveMutation(0.6),
+                    Some(HillEvents::ImproveMutation(0.15)),
+                ),
+                upgrade(
+                    "found_nest",
+                    "Found New Nest",
+                    "Send a group of ants to start a new hill near a far away food heap.",
+                    (25, 25),
+                    HillEvents::FoundNest { count: 20 },
+                    None,
+                ),
+            ],
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct UpgradeLevels(pub HashMap<String, u32>);
+
+impl UpgradeLevels {
+    pub fn level(&self, id: &str) -> u32 {
+        *self.0.get(id).unwrap_or(&0)
+    }
+
+    pub fn cost(&self, upgrade: &Upgrade) -> u32 {
+        upgrade.cost.at_level(self.level(&upgrade.id))
+    }
+
+    pub fn is_unlocked(&self, upgrade: &Upgrade) -> bool {
+        upgrade.requires.iter().all(|id| self.level(id) > 0)
+    }
+
+    pub fn is_maxed(&self, upgrade: &Upgrade) -> bool {
+        upgrade
+            .max_level
+            .map(|max| self.level(&upgrade.id) >= max)
+            .unwrap_or(false)
+    }
+
+    /// Pays for `upgrade` out of `queen_food` and returns the events to send, or nothing when
+    /// it can't be afforded
+    pub fn buy(&mut self, upgrade: &Upgrade, queen_food: &mut u32) -> Option<Vec<HillEvents>> {
+        let level = self.level(&upgrade.id);
+        *queen_food = queen_food.checked_sub(upgrade.cost.at_level(level))?;
+        let mut events: Vec<_> = upgrade.effects_at_level(level).collect();
+        for boost in &upgrade.boosted_by {
+            let boost_level = self.level(&boost.upgrade);
+            for (event, increase) in events.iter_mut().zip(&boost.effects_per_level) {
+                *event = event.increased_by(increase, boost_level);
+            }
+        }
+        self.0.insert(upgrade.id.clone(), level + 1);
+        Some(events)
+    }
+}